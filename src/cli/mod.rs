@@ -1,4 +1,7 @@
-use clap::{Parser, Subcommand};
+use crate::utils::config::Config;
+use crate::utils::error::Error;
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -9,8 +12,76 @@ pub struct Cli {
 
 #[derive(Subcommand)]
 pub enum Commands {
+    /// 启动开发服务器
     Dev {
-        #[arg(short, long, default_value = "127.0.0.1:8080")]
-        port: String,
+        #[command(flatten)]
+        config: ConfigArgs,
+        /// 端口，也可以写成 host:port，默认 8080
+        #[arg(short, long)]
+        port: Option<String>,
+        /// 监听的地址，默认 127.0.0.1
+        #[arg(long)]
+        host: Option<String>,
     },
+    /// 生产构建，产物输出到 outDir（默认 dist）
+    Build {
+        #[command(flatten)]
+        config: ConfigArgs,
+    },
+}
+
+/// 各个子命令都能用的参数，会覆盖配置文件里的值
+#[derive(Args)]
+pub struct ConfigArgs {
+    /// 配置文件，默认在项目根目录找 arashi.config.json / arashi.toml
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// 项目根目录（index.html 所在的目录）
+    #[arg(long)]
+    pub root: Option<PathBuf>,
+    /// 部署的公共路径
+    #[arg(long)]
+    pub base: Option<String>,
+}
+
+impl ConfigArgs {
+    /// 从当前目录（或者 --root）加载配置文件，再用命令行参数覆盖
+    pub fn load(&self, cwd: &std::path::Path) -> Result<Config, Error> {
+        let dir = match &self.root {
+            Some(root) => cwd.join(root),
+            None => cwd.to_path_buf(),
+        };
+        let mut config = Config::load(&dir, self.config.as_deref())?;
+        if self.root.is_some() {
+            config.set_root(&dir);
+        }
+        if let Some(base) = &self.base {
+            config.set_base(base);
+        }
+        Ok(config)
+    }
+}
+
+/// --port 8080 或者 --port 127.0.0.1:8080（以前的写法）
+pub fn apply_server_args(
+    config: &mut Config,
+    host: Option<&str>,
+    port: Option<&str>,
+) -> Result<(), Error> {
+    if let Some(port) = port {
+        let port = match port.rsplit_once(':') {
+            Some((host, port)) => {
+                config.server.host = host.to_string();
+                port
+            }
+            None => port,
+        };
+        config.server.port = port
+            .parse()
+            .map_err(|_| Error::config(None, format!("端口不合法: {}", port)))?;
+    }
+    if let Some(host) = host {
+        config.server.host = host.to_string();
+    }
+    Ok(())
 }
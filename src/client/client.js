@@ -0,0 +1,284 @@
+// Arashi HMR 客户端运行时
+// 开发服务器会把它注入到 index.html 中，通过 WebSocket 接收服务端推送的更新，
+// 同时给每个模块提供 import.meta.hot API（由 createHotContext 创建）
+
+// 开发服务器的 base（去掉结尾的 /），返回这个脚本的时候替换成配置里的值
+const base = __ARASHI_BASE__;
+const socketProtocol = location.protocol === "https:" ? "wss" : "ws";
+const socket = new WebSocket(`${socketProtocol}://${location.host}${base}/__arashi_hmr`);
+
+// ownerPath -> { id, callbacks: [{ deps, fn }] }，记录哪些模块接受了哪些依赖的更新
+const hotModulesMap = new Map();
+// ownerPath -> dispose 回调，模块被替换之前调用
+const disposeMap = new Map();
+// ownerPath -> import.meta.hot.data，在模块的新旧实例之间共享
+const dataMap = new Map();
+
+socket.addEventListener("message", ({ data }) => {
+  handleMessage(JSON.parse(data));
+});
+
+// 服务端断开（比如重启）之后，轮询等它恢复再刷新页面
+socket.addEventListener("close", async ({ wasClean }) => {
+  if (wasClean) return;
+  console.log("[arashi] server connection lost. polling for restart...");
+  await waitForSuccessfulPing();
+  location.reload();
+});
+
+async function handleMessage(payload) {
+  switch (payload.type) {
+    case "connected":
+      console.log("[arashi] connected.");
+      break;
+    case "update":
+      // 出错的文件改好之后会触发更新，浮层跟着消失
+      clearErrorOverlay();
+      payload.updates.forEach((update) => {
+        if (update.type === "css-update") {
+          updateLink(update);
+        } else {
+          queueUpdate(fetchUpdate(update));
+        }
+      });
+      break;
+    case "full-reload":
+      location.reload();
+      break;
+    case "error":
+      console.error(`[arashi] ${payload.err.message}`);
+      showErrorOverlay(payload.err);
+      break;
+    default:
+      console.warn("[arashi] unknown payload:", payload);
+  }
+}
+
+let pending = false;
+let queued = [];
+
+// 同一批更新可能涉及多个模块，先全部 import 完再按顺序执行回调，
+// 避免某个回调拿到的是还没更新完的模块
+async function queueUpdate(p) {
+  queued.push(p);
+  if (!pending) {
+    pending = true;
+    await Promise.resolve();
+    pending = false;
+    const loading = [...queued];
+    queued = [];
+    (await Promise.all(loading)).forEach((fn) => fn && fn());
+  }
+}
+
+async function fetchUpdate({ path, acceptedPath, timestamp }) {
+  const mod = hotModulesMap.get(path);
+  const qualifiedCallbacks = mod
+    ? mod.callbacks.filter(({ deps }) => deps.includes(acceptedPath))
+    : [];
+
+  // 没有任何模块接受这次更新，只能整页刷新
+  if (qualifiedCallbacks.length === 0) {
+    location.reload();
+    return;
+  }
+
+  const disposer = disposeMap.get(acceptedPath);
+  if (disposer) await disposer(dataMap.get(acceptedPath));
+
+  let fetchedModule;
+  try {
+    // 加上时间戳绕过浏览器的 ESM 缓存，拿到新的模块实例，?raw 这类模块本身带着查询参数
+    const separator = acceptedPath.includes("?") ? "&" : "?";
+    fetchedModule = await import(`${acceptedPath}${separator}t=${timestamp}`);
+  } catch (e) {
+    console.error(`[arashi] failed to fetch updated module ${acceptedPath}.`, e);
+    return;
+  }
+
+  return () => {
+    for (const { deps, fn } of qualifiedCallbacks) {
+      fn(deps.map((dep) => (dep === acceptedPath ? fetchedModule : undefined)));
+    }
+    console.log(`[arashi] hot updated: ${acceptedPath}`);
+  };
+}
+
+// 已经在替换的 <link>，同一个文件连着改几次的时候不会重复替换
+const outdatedLinks = new WeakSet();
+
+// index.html 里 <link rel="stylesheet"> 引用的 css 变了：插入一个带时间戳的新 link，
+// 加载完再删掉旧的，页面不会闪
+function updateLink({ path, timestamp }) {
+  const links = document.querySelectorAll('link[rel="stylesheet"]');
+  for (const link of links) {
+    const url = new URL(link.href, location.href);
+    if (url.pathname !== path || outdatedLinks.has(link)) continue;
+    outdatedLinks.add(link);
+    url.searchParams.set("t", timestamp);
+    const next = link.cloneNode();
+    next.href = url.href;
+    const removeOld = () => link.remove();
+    next.addEventListener("load", removeOld);
+    next.addEventListener("error", removeOld);
+    link.after(next);
+    console.log(`[arashi] css hot updated: ${path}`);
+  }
+}
+
+// id -> 导入的 css 对应的 <style>，模块重新执行的时候原地更新，不会越插越多
+const sheetsMap = new Map();
+
+export function updateStyle(id, content) {
+  let style = sheetsMap.get(id);
+  if (!style) {
+    style = document.createElement("style");
+    style.setAttribute("type", "text/css");
+    style.setAttribute("data-arashi-id", id);
+    document.head.appendChild(style);
+    sheetsMap.set(id, style);
+  }
+  style.textContent = content;
+}
+
+export function removeStyle(id) {
+  const style = sheetsMap.get(id);
+  if (style) {
+    style.remove();
+    sheetsMap.delete(id);
+  }
+}
+
+// 转换、解析出错时覆盖整个页面的错误浮层，点击空白处或者按 Esc 关闭
+const overlayId = "arashi-error-overlay";
+const overlayTemplate = `
+<style>
+  .backdrop {
+    position: fixed; inset: 0; z-index: 99999; overflow: auto;
+    background: rgba(0, 0, 0, 0.66); font-family: Menlo, Consolas, monospace;
+  }
+  .window {
+    max-width: 960px; margin: 60px auto; padding: 24px 28px; border-top: 6px solid #ff5555;
+    background: #181818; color: #d8d8d8; box-shadow: 0 12px 32px rgba(0, 0, 0, 0.5);
+    border-radius: 6px; font-size: 14px; line-height: 1.5;
+  }
+  .plugin { color: #cfa4ff; margin-bottom: 8px; }
+  .message { color: #ff5555; font-weight: 600; white-space: pre-wrap; margin: 0 0 12px; }
+  .file { color: #2dd9da; margin-bottom: 12px; }
+  .frame { color: #e2aa53; background: #0d0d0d; padding: 12px; overflow: auto; margin: 0; }
+  .tip { color: #888; font-size: 12px; margin-top: 16px; }
+</style>
+<div class="backdrop">
+  <div class="window">
+    <div class="plugin"></div>
+    <pre class="message"></pre>
+    <div class="file"></div>
+    <pre class="frame"></pre>
+    <div class="tip">修改文件后浮层会自动消失，点击空白处或者按 Esc 关闭</div>
+  </div>
+</div>
+`;
+
+function showErrorOverlay(err) {
+  clearErrorOverlay();
+  const overlay = document.createElement("div");
+  overlay.id = overlayId;
+  const root = overlay.attachShadow({ mode: "open" });
+  root.innerHTML = overlayTemplate;
+  root.querySelector(".plugin").textContent = `[plugin:${err.plugin}] ${err.id}`;
+  root.querySelector(".message").textContent = err.message;
+  const file = root.querySelector(".file");
+  if (err.loc) {
+    file.textContent =
+      err.loc.line != null ? `${err.loc.file}:${err.loc.line}:${err.loc.column}` : err.loc.file;
+  } else {
+    file.remove();
+  }
+  const frame = root.querySelector(".frame");
+  if (err.frame) {
+    frame.textContent = err.frame;
+  } else {
+    frame.remove();
+  }
+  root.querySelector(".backdrop").addEventListener("click", (e) => {
+    if (e.target === e.currentTarget) clearErrorOverlay();
+  });
+  document.body.appendChild(overlay);
+}
+
+function clearErrorOverlay() {
+  document.getElementById(overlayId)?.remove();
+}
+
+document.addEventListener("keydown", (e) => {
+  if (e.key === "Escape") clearErrorOverlay();
+});
+
+async function waitForSuccessfulPing(ms = 1000) {
+  while (true) {
+    try {
+      await fetch(`${location.protocol}//${location.host}${base}/@arashi/client`);
+      break;
+    } catch (e) {
+      await new Promise((resolve) => setTimeout(resolve, ms));
+    }
+  }
+}
+
+// 依赖路径相对于当前模块解析成服务端的 url，例如 ./Comp.tsx => /src/components/Comp.tsx
+function resolveDep(ownerPath, dep) {
+  return new URL(dep, `${location.origin}${ownerPath}`).pathname;
+}
+
+export function createHotContext(ownerPath) {
+  if (!dataMap.has(ownerPath)) {
+    dataMap.set(ownerPath, {});
+  }
+
+  // 模块被重新执行时，清掉旧实例注册的回调，由新实例重新注册
+  const mod = hotModulesMap.get(ownerPath);
+  if (mod) {
+    mod.callbacks = [];
+  }
+
+  function acceptDeps(deps, callback = () => {}) {
+    const mod = hotModulesMap.get(ownerPath) || { id: ownerPath, callbacks: [] };
+    mod.callbacks.push({ deps, fn: callback });
+    hotModulesMap.set(ownerPath, mod);
+  }
+
+  return {
+    get data() {
+      return dataMap.get(ownerPath);
+    },
+
+    accept(deps, callback) {
+      if (typeof deps === "function" || !deps) {
+        // import.meta.hot.accept() / accept(mod => {})：接受自身的更新
+        acceptDeps([ownerPath], ([mod]) => deps && deps(mod));
+      } else if (typeof deps === "string") {
+        // import.meta.hot.accept('./dep', mod => {})
+        const dep = resolveDep(ownerPath, deps);
+        acceptDeps([dep], ([mod]) => callback && callback(mod));
+      } else if (Array.isArray(deps)) {
+        // import.meta.hot.accept(['./a', './b'], ([a, b]) => {})
+        acceptDeps(
+          deps.map((dep) => resolveDep(ownerPath, dep)),
+          callback
+        );
+      } else {
+        throw new Error("invalid hot.accept() usage.");
+      }
+    },
+
+    dispose(cb) {
+      disposeMap.set(ownerPath, cb);
+    },
+
+    // 模块自己判断无法热更新时调用，交给服务端向上查找新的边界
+    invalidate(message) {
+      socket.send(JSON.stringify({ type: "invalidate", path: ownerPath, message }));
+      console.log(`[arashi] invalidate ${ownerPath}` + (message ? `: ${message}` : ""));
+    },
+  };
+}
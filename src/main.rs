@@ -1,35 +1,124 @@
 mod cli;
 mod middleware;
+mod plugins;
 mod utils;
 
-use crate::middleware::css_transform::CssTransform;
+use crate::middleware::base::BasePath;
 use crate::middleware::dependency_analysis::DependencyAnalysis;
+use crate::middleware::hmr::HmrClient;
+use crate::middleware::logger::Logger;
+use crate::middleware::plugin_transform::PluginTransform;
 use crate::middleware::static_file::StaticFiles;
-use crate::middleware::tsx_transform::TypescriptTransform;
+use crate::plugins::asset::AssetPlugin;
+use crate::plugins::css::CssPlugin;
+use crate::plugins::deps::DepsPlugin;
+use crate::plugins::glob::GlobPlugin;
+use crate::plugins::json::JsonPlugin;
+use crate::plugins::typescript::TypescriptPlugin;
+use crate::plugins::{Command, PluginContainer};
 use clap::Parser;
-use cli::{Cli, Commands};
+use cli::{apply_server_args, Cli, Commands};
+use std::sync::Arc;
+use tide_websockets::WebSocket;
+use tokio::sync::RwLock;
+use utils::build;
+use utils::config::Config;
 use utils::fs;
+use utils::hmr::{self, HmrServer};
+use utils::module_graph::ModuleGraph;
+use utils::transform_cache::TransformCache;
+use utils::watcher::FileWatcher;
 // use utils::prebuild;
 
-async fn start_server(url: &str) {
+async fn start_server(config: Config) {
     let mut app = tide::new();
-    let working_dir = fs::get_current_dir().unwrap().to_string_lossy().to_string();
+    let config = Arc::new(config);
+    let url = config.address();
+    let react_refresh = hmr::has_react_refresh(&config.root);
+    let transform_cache = TransformCache::new();
+    let module_graph = Arc::new(RwLock::new(ModuleGraph::new()));
+    let deps = match DepsPlugin::new(config.clone()).await {
+        Ok(deps) => deps,
+        Err(e) => {
+            println!("[deps] {}", e);
+            std::process::exit(1);
+        }
+    };
+    // 启动时就把依赖预构建好，避免首次请求时的瀑布流
+    deps.optimize_deps().await;
+    let project_plugins = match plugins::project_plugins(&config, Command::Serve).await {
+        Ok(project_plugins) => project_plugins,
+        Err(e) => {
+            println!("[plugins] {}", e);
+            std::process::exit(1);
+        }
+    };
+    let plugins = Arc::new(PluginContainer::new(
+        config.clone(),
+        Command::Serve,
+        vec![
+            Box::new(AssetPlugin),
+            Box::new(TypescriptPlugin::new()),
+            Box::new(GlobPlugin),
+            Box::new(CssPlugin),
+            Box::new(JsonPlugin),
+            Box::new(deps.clone()),
+        ],
+        project_plugins,
+    ));
+    let hmr_server = HmrServer::new(config.clone(), module_graph.clone(), plugins.clone());
+
+    // 先去掉请求路径里的 base
+    app.with(BasePath::new(config.base.clone()));
+    // 0. 注入 HMR 客户端，把后面出的错发给浏览器
+    app.with(HmrClient::new(
+        react_refresh,
+        hmr_server.clone(),
+        plugins.clone(),
+    ));
+    // 1. 依赖分析，用插件改写导入路径
+    app.with(DependencyAnalysis::new(
+        config.clone(),
+        module_graph,
+        plugins.clone(),
+    ));
+    // 2. 插件的 load / transform：TypeScript、CSS、预构建的依赖
+    app.with(PluginTransform::new(
+        plugins.clone(),
+        transform_cache.clone(),
+    ));
+    // 3. 静态文件服务
+    app.with(StaticFiles::new(config.clone()));
+
+    // 文件监听：先让缓存失效，再通知浏览器更新，顺序不能反
+    let mut watcher = FileWatcher::new(config.clone());
+    watcher.subscribe(Arc::new(Logger {}));
+    watcher.subscribe(Arc::new(transform_cache));
+    watcher.subscribe(Arc::new(deps));
+    watcher.subscribe(Arc::new(hmr_server.clone()));
+    if let Err(e) = watcher.start() {
+        println!("[watcher] failed to watch {}: {}", config.root.display(), e);
+    }
 
-    // 1. 按需处理 node_modules 和依赖分析
-    app.with(DependencyAnalysis::new(working_dir.clone()).await);
-    // 2. TypeScript 转换
-    app.with(TypescriptTransform::new(working_dir.clone()));
-    // 3. CSS 转换
-    app.with(CssTransform::new(working_dir.clone()));
-    // 4. 静态文件服务
-    app.with(StaticFiles::new(working_dir.clone()));
+    // HMR 的 WebSocket 连接，文件变化时通过它通知浏览器
+    app.at(&config.server_url(hmr::HMR_PATH))
+        .get(WebSocket::new(move |_req, stream| {
+            let hmr_server = hmr_server.clone();
+            async move { hmr_server.handle_connection(stream).await }
+        }));
 
     println!(
-        "========== 启动 ==========\n URL: http://{} \n========== RUST ==========",
-        url
+        "========== 启动 ==========\n URL: http://{}{} \n========== RUST ==========",
+        url, config.base
     );
+    if let Some(file) = &config.config_file {
+        println!("[config] {}", file.display());
+    }
+    println!("[plugins] {}", plugins.names().join(", "));
     // 这里必须要 await 一下，不然 cli 命令之后就结束了不会等待阻塞
-    app.listen(url).await;
+    if let Err(e) = app.listen(url).await {
+        println!("启动失败: {}", e);
+    }
 }
 
 #[async_std::main]
@@ -37,8 +126,33 @@ async fn main() {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Dev { port } => {
-            start_server(&port).await;
+        Commands::Dev { config, port, host } => {
+            let cwd = fs::get_current_dir().expect("无法获取当前目录");
+            let config = config.load(&cwd).and_then(|mut config| {
+                apply_server_args(&mut config, host.as_deref(), port.as_deref())?;
+                Ok(config)
+            });
+            match config {
+                Ok(config) => start_server(config).await,
+                Err(e) => {
+                    println!("[config] {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Build { config } => {
+            let cwd = fs::get_current_dir().expect("无法获取当前目录");
+            let config = match config.load(&cwd) {
+                Ok(config) => config,
+                Err(e) => {
+                    println!("[config] {}", e);
+                    std::process::exit(1);
+                }
+            };
+            if let Err(e) = build::build(&config) {
+                println!("[build] {}", e);
+                std::process::exit(1);
+            }
         }
     }
 }
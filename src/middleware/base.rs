@@ -0,0 +1,36 @@
+use tide::{Next, Request};
+
+/// 配置了 base（比如 /app/）的时候，把请求路径里的 base 去掉，后面的中间件都按根路径处理。
+/// 不带 base 的请求（改写过的 /@modules/ 导入、HMR 客户端）原样放过去
+#[derive(Debug, Clone)]
+pub struct BasePath {
+    pub base: String,
+}
+
+impl BasePath {
+    pub fn new(base: String) -> Self {
+        BasePath { base }
+    }
+}
+
+#[async_trait::async_trait]
+impl<State: Clone + Send + Sync + 'static> tide::Middleware<State> for BasePath {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        if self.base != "/" {
+            let path = req.url().path();
+            // /app 和 /app/ 都是首页
+            let stripped = if path == self.base.trim_end_matches('/') {
+                Some("/".to_string())
+            } else {
+                path.strip_prefix(self.base.trim_end_matches('/'))
+                    .filter(|rest| rest.starts_with('/'))
+                    .map(str::to_string)
+            };
+            if let Some(stripped) = stripped {
+                let http: &mut tide::http::Request = req.as_mut();
+                http.url_mut().set_path(&stripped);
+            }
+        }
+        Ok(next.run(req).await)
+    }
+}
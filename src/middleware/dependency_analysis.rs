@@ -1,62 +1,222 @@
-use crate::utils::prebuild::DepCache;
-use crate::utils::transform::{is_js_or_ts_file, process_imports, resolve_module_path};
-use async_std::path::Path;
+use crate::middleware::plugin_transform::{WatchDirs, WatchFiles};
+use crate::plugins::asset::needs_import_query;
+use crate::plugins::{clean_id, PluginContainer};
+use crate::utils::config::{Config, SourcemapMode};
+use crate::utils::css::is_stylesheet_request;
+use crate::utils::hmr::inject_hot_context;
+use crate::utils::import_analysis::analyze_imports;
+use crate::utils::module_graph::{module_url, resolve_url, ModuleGraph};
+use crate::utils::sourcemap::{append_inline_map, extract_inline_map, CodeEdits};
+use std::collections::HashSet;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tide::{Next, Request, Response, StatusCode};
+use tide::{Next, Request, Response};
 use tokio::sync::RwLock;
 
 #[derive(Clone)]
 pub struct DependencyAnalysis {
-    root_dir: String,
-    dep_cache: Arc<RwLock<DepCache>>,
+    config: Arc<Config>,
+    module_graph: Arc<RwLock<ModuleGraph>>,
+    plugins: Arc<PluginContainer>,
 }
 
 impl DependencyAnalysis {
-    pub async fn new(root_dir: String) -> Self {
-        let dep_cache = Arc::new(RwLock::new(DepCache::new(Path::new(&root_dir)).await));
+    pub fn new(
+        config: Arc<Config>,
+        module_graph: Arc<RwLock<ModuleGraph>>,
+        plugins: Arc<PluginContainer>,
+    ) -> Self {
         Self {
-            root_dir,
-            dep_cache,
+            config,
+            module_graph,
+            plugins,
         }
     }
+
+    fn file_of(&self, url: &str) -> Option<PathBuf> {
+        if url.starts_with("/@modules/") {
+            return None;
+        }
+        Some(self.config.root.join(clean_id(url).trim_start_matches('/')))
+    }
+
+    fn url_of(&self, file: &std::path::Path) -> Option<String> {
+        let relative = file.strip_prefix(&self.config.root).ok()?;
+        Some(format!(
+            "/{}",
+            relative.to_string_lossy().replace('\\', "/")
+        ))
+    }
+
+    /// 用 AST 分析模块的导入，改写导入路径并记录到模块图里：
+    /// 1. 插件的 resolve_id 改写导入路径，比如第三方依赖改写成 /@modules/ 开头
+    /// 2. js 导入的图片这类资源和 .json 加上 ?import，和 <img src>、fetch() 请求的文件本身区分开
+    /// 3. 热更新过的依赖要在导入路径后面加上 ?t=，不然浏览器会直接用缓存里的旧模块
+    ///
+    /// 返回的就是最终给浏览器的代码，同时作为转换结果缓存在模块图里。
+    /// 前面的转换带了 source map 的话，改完之后的代码也有对应的 source map。
+    /// watch_files 是转换的时候读过的其他文件，也当成依赖记下来，它们变了会沿着模块图更新这个模块；
+    /// glob_dirs 里新建、删除文件的时候 HMR 会重新转换这个模块
+    async fn analyze_module(
+        &self,
+        url: &str,
+        body: String,
+        watch_files: Vec<PathBuf>,
+        glob_dirs: Vec<PathBuf>,
+    ) -> String {
+        let (code, map) = extract_inline_map(&body);
+        let analysis = analyze_imports(code);
+        // 先用插件解析所有导入，解析的时候不拿着模块图的锁
+        let mut resolved = Vec::new();
+        for import in &analysis.imports {
+            if import.specifier.starts_with("http") || import.specifier.starts_with("data:") {
+                continue;
+            }
+            // 先替换别名，'@/utils' => '/src/utils'
+            let aliased = self.config.apply_alias(&import.specifier);
+            let source = aliased.as_deref().unwrap_or(&import.specifier);
+            let mut specifier = match self.plugins.resolve_id(source, url).await {
+                Ok(Some(id)) => id,
+                Ok(None) => source.to_string(),
+                Err(e) => {
+                    println!("[plugins] {}", e);
+                    source.to_string()
+                }
+            };
+            if needs_import_query(&specifier) && !specifier.starts_with("/@modules/") {
+                specifier.push_str("?import");
+            }
+            let dep_url = if specifier.starts_with('/') {
+                specifier.clone()
+            } else {
+                resolve_url(url, &specifier)
+            };
+            resolved.push((import, specifier, dep_url));
+        }
+
+        let mut graph = self.module_graph.write().await;
+        graph.ensure_entry(url, self.file_of(url));
+        graph.set_glob_dirs(url, glob_dirs);
+
+        let mut imported = HashSet::new();
+        let mut edits = CodeEdits::new();
+        for (import, specifier, dep_url) in resolved {
+            let dep = graph.ensure_entry(&dep_url, self.file_of(&dep_url));
+            if dep.last_hmr_timestamp > 0 {
+                let separator = if specifier.contains('?') { '&' } else { '?' };
+                edits.replace(
+                    import.start,
+                    import.end,
+                    format!("{}{}t={}", specifier, separator, dep.last_hmr_timestamp),
+                );
+            } else if specifier != import.specifier {
+                edits.replace(import.start, import.end, specifier);
+            }
+            imported.insert(dep_url);
+        }
+        for file in watch_files {
+            if let Some(dep_url) = self.url_of(&file) {
+                graph.ensure_entry(&dep_url, Some(file));
+                imported.insert(dep_url);
+            }
+        }
+        let accepted_hmr_deps = analysis
+            .accepted_deps
+            .iter()
+            .map(|dep| resolve_url(url, dep))
+            .collect();
+
+        // 用到了 import.meta.hot 的模块，需要先拿到属于自己的 hot context
+        if code.contains("import.meta.hot") {
+            inject_hot_context(&mut edits, url, &self.config);
+        }
+        let mut result = edits.apply(code);
+        let map = map.map(|map| edits.remap(&map, code, &result));
+        match (&map, self.config.server.sourcemap) {
+            (Some(map), SourcemapMode::Inline) => result = append_inline_map(&result, map),
+            (Some(_), SourcemapMode::File) => {
+                let file_name = url.rsplit('/').next().unwrap_or(url);
+                result.push_str(&format!("\n//# sourceMappingURL={}.map\n", file_name));
+            }
+            _ => {}
+        }
+
+        graph.update_module_info(
+            url,
+            imported,
+            accepted_hmr_deps,
+            analysis.self_accepting,
+            result.clone(),
+            map.map(|map| map.to_json_string()),
+        );
+        result
+    }
 }
 
 #[async_trait::async_trait]
 impl<State: Clone + Send + Sync + 'static> tide::Middleware<State> for DependencyAnalysis {
     async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
         let path = req.url().path().to_string();
+        let url = module_url(req.url());
+        // <link rel="stylesheet"> 引用的 css 原样返回，不用分析；
+        // 预构建的依赖里导入路径已经改写好了，重新预构建之后也不能用模块图里的旧结果
+        let analyzable = !is_stylesheet_request(&req) && !url.starts_with("/@modules/");
 
-        if path.starts_with("/@modules/") {
-            let module_name = path.trim_start_matches("/@modules/");
+        // server.sourcemap = "file" 的时候，模块最后指向的 xxx.tsx.map
+        if let Some(module_url) = path.strip_suffix(".map") {
+            let map = self
+                .module_graph
+                .read()
+                .await
+                .get_module(module_url)
+                .and_then(|node| node.transform_map.clone());
+            if let Some(map) = map {
+                return Ok(Response::builder(200)
+                    .content_type("application/json")
+                    .body(map)
+                    .build());
+            }
+        }
 
-            // 尝试获取或构建模块
-            if let Some(pkg_path) = resolve_module_path(&self.root_dir, module_name).await {
-                match self
-                    .dep_cache
-                    .write()
-                    .await
-                    .get_or_build(module_name, &pkg_path)
-                    .await
-                {
-                    Ok(cached_path) => {
-                        return Ok(Response::builder(200)
-                            .content_type("application/javascript")
-                            .body(async_std::fs::read_to_string(cached_path).await?)
-                            .build());
-                    }
-                    Err(_) => return Ok(Response::new(StatusCode::InternalServerError)),
-                }
+        if analyzable {
+            // 模块图里有转换结果说明文件没变过，直接返回
+            let cached = self
+                .module_graph
+                .read()
+                .await
+                .get_module(&url)
+                .and_then(|node| node.transform_result.clone());
+            if let Some(code) = cached {
+                return Ok(Response::builder(200)
+                    .content_type("application/javascript")
+                    .body(code)
+                    .build());
             }
-            return Ok(Response::new(StatusCode::NotFound));
         }
 
         let mut response = next.run(req).await;
 
-        if is_js_or_ts_file(&path) {
-            if let Some(body) = response.take_body().into_string().await.ok() {
+        // 不看后缀，返回的是 js 就分析：插件 load / transform 的结果都是 js（包括 .vue 这种自定义类型和虚拟模块），
+        // 导入的 css、json、?raw 这类资源也已经转成了 js
+        let is_javascript = response
+            .content_type()
+            .map(|mime| mime.essence().ends_with("javascript"))
+            .unwrap_or(false);
+        if analyzable && is_javascript && response.status().is_success() {
+            let watch_files = response
+                .ext::<WatchFiles>()
+                .map(|files| files.0.clone())
+                .unwrap_or_default();
+            let glob_dirs = response
+                .ext::<WatchDirs>()
+                .map(|dirs| dirs.0.clone())
+                .unwrap_or_default();
+            if let Ok(body) = response.take_body().into_string().await {
                 // 当一个文件中有 import，将其中第三方依赖的导入路径特殊标记一下
                 // 后续真正请求这些第三方模块的时候，识别到这些标记，就走上面的代码逻辑
-                let processed_content = process_imports(body).await;
+                let processed_content = self
+                    .analyze_module(&url, body, watch_files, glob_dirs)
+                    .await;
                 response.set_content_type("application/javascript");
                 response.set_body(processed_content);
             }
@@ -0,0 +1,62 @@
+use crate::plugins::PluginContainer;
+use crate::utils::hmr::{
+    client_script, inject_client_script, ErrorPayload, HmrServer, CLIENT_PATH,
+};
+use std::sync::Arc;
+use tide::{Next, Request, Response, StatusCode};
+
+#[derive(Clone)]
+pub struct HmrClient {
+    pub react_refresh: bool,
+    pub hmr_server: HmrServer,
+    pub plugins: Arc<PluginContainer>,
+}
+
+impl HmrClient {
+    pub fn new(react_refresh: bool, hmr_server: HmrServer, plugins: Arc<PluginContainer>) -> Self {
+        HmrClient {
+            react_refresh,
+            hmr_server,
+            plugins,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<State: Clone + Send + Sync + 'static> tide::Middleware<State> for HmrClient {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        // 客户端运行时是打包进二进制里的，不需要读文件
+        if req.url().path() == CLIENT_PATH {
+            let mut res = Response::new(StatusCode::Ok);
+            res.set_content_type("application/javascript");
+            res.set_body(client_script(self.plugins.config()));
+            return Ok(res);
+        }
+
+        let mut response = next.run(req).await;
+
+        // 后面的中间件转换、解析失败的时候会把错误挂在 response 上，发给浏览器显示错误浮层
+        if let Some(err) = response.ext::<ErrorPayload>() {
+            self.hmr_server.send_error(err.clone()).await;
+        }
+
+        // 先让插件转换 html，再注入客户端脚本，浏览器加载页面后就会连上 WebSocket
+        let is_html = response
+            .content_type()
+            .map(|mime| mime.essence() == "text/html")
+            .unwrap_or(false);
+        if is_html {
+            if let Ok(html) = response.take_body().into_string().await {
+                let html = self.plugins.transform_index_html(html);
+                response.set_body(inject_client_script(
+                    &html,
+                    self.react_refresh,
+                    self.plugins.config(),
+                ));
+                response.set_content_type("text/html;charset=utf-8");
+            }
+        }
+
+        Ok(response)
+    }
+}
@@ -1,3 +1,4 @@
+use crate::utils::watcher::{WatchEvent, WatchListener};
 use tide::{Next, Request};
 #[derive(Debug, Clone)]
 pub struct Logger {}
@@ -10,3 +11,10 @@ impl<State: Clone + Send + Sync + 'static> tide::Middleware<State> for Logger {
         Ok(response)
     }
 }
+
+#[async_trait::async_trait]
+impl WatchListener for Logger {
+    async fn on_change(&self, event: &WatchEvent) {
+        println!("[watcher] {:?}: {}", event.kind, event.path.display());
+    }
+}
@@ -1,5 +1,6 @@
-pub mod css_transform;
+pub mod base;
 pub mod dependency_analysis;
+pub mod hmr;
 pub mod logger;
+pub mod plugin_transform;
 pub mod static_file;
-pub mod tsx_transform;
@@ -0,0 +1,129 @@
+use crate::plugins::{clean_id, ModuleSource, PluginContainer};
+use crate::utils::css::is_stylesheet_request;
+use crate::utils::error::Error;
+use crate::utils::fs;
+use crate::utils::module_graph::module_url;
+use crate::utils::sourcemap::append_inline_map;
+use crate::utils::transform_cache::{Entry, TransformCache};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tide::{Next, Request, Response, StatusCode};
+
+/// 转换结果依赖的其他文件（比如 css 里 @import 的文件），挂在 response 上，
+/// 由 DependencyAnalysis 记到模块图里，它们变了才能找到要热更新的模块
+#[derive(Debug, Clone)]
+pub struct WatchFiles(pub Vec<PathBuf>);
+
+/// 转换结果里 import.meta.glob 匹配的目录，里面增删文件的时候要重新展开
+#[derive(Debug, Clone)]
+pub struct WatchDirs(pub Vec<PathBuf>);
+
+/// 用插件的 load / transform 处理请求的模块
+///
+/// 插件没有 load 的话读项目里的文件，没有插件转换过的文件交给后面的 StaticFiles 原样返回。
+/// source map 以 data url 的形式带在代码最后，
+/// 前面的 DependencyAnalysis 改完导入路径之后再按配置决定内联还是单独提供
+#[derive(Clone)]
+pub struct PluginTransform {
+    pub plugins: Arc<PluginContainer>,
+    pub cache: TransformCache,
+}
+
+impl PluginTransform {
+    pub fn new(plugins: Arc<PluginContainer>, cache: TransformCache) -> Self {
+        PluginTransform { plugins, cache }
+    }
+}
+
+#[async_trait::async_trait]
+impl<State: Clone + Send + Sync + 'static> tide::Middleware<State> for PluginTransform {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        // ?raw、?url 这类查询参数也是模块 id 的一部分
+        let id = module_url(req.url());
+        // <link rel="stylesheet"> 引用的 css 交给 StaticFiles 原样返回
+        if is_stylesheet_request(&req) {
+            return Ok(next.run(req).await);
+        }
+
+        // 插件提供的模块，比如 /@modules/react
+        let loaded = match self.plugins.load(&id).await {
+            Ok(loaded) => loaded,
+            Err(e) => return Ok(e.into_response(&id)),
+        };
+        if let Some(source) = loaded {
+            return Ok(match self.plugins.transform(source.clone(), &id).await {
+                Ok(transformed) => javascript(render(transformed.unwrap_or(source))),
+                Err(e) => e.into_response(&id),
+            });
+        }
+
+        let file_path = self
+            .plugins
+            .config()
+            .root
+            .join(clean_id(&id).trim_start_matches('/'));
+        // 文件没有变化过就直接用缓存，文件变化时 watcher 会让缓存失效
+        if let Some(entry) = self.cache.get(&id).await {
+            return Ok(with_watch(entry));
+        }
+        if !file_path.is_file() {
+            // 浏览器 import 的模块不存在的话显示在错误浮层上，图片之类的就只是 404
+            let is_script = req
+                .header("Sec-Fetch-Dest")
+                .map(|dest| dest.as_str() == "script")
+                .unwrap_or(true);
+            let response = next.run(req).await;
+            if response.status() == StatusCode::NotFound && is_script {
+                let e = std::io::Error::from(std::io::ErrorKind::NotFound);
+                return Ok(Error::io(file_path, e).into_response(&id));
+            }
+            return Ok(response);
+        }
+        // 图片这类二进制文件不会被转换
+        let Ok(code) = fs::read_file_content(&file_path) else {
+            return Ok(next.run(req).await);
+        };
+
+        match self.plugins.transform(ModuleSource::new(code), &id).await {
+            Ok(Some(source)) => {
+                let watch_files = source.watch_files.clone();
+                let watch_dirs = source.watch_dirs.clone();
+                let entry = Entry {
+                    code: render(source),
+                    watch_files,
+                    watch_dirs,
+                };
+                self.cache.insert(id, file_path, entry.clone()).await;
+                Ok(with_watch(entry))
+            }
+            Ok(None) => Ok(next.run(req).await),
+            // 出错的时候不缓存，文件修好之后重新转换
+            Err(e) => Ok(e.into_response(&id)),
+        }
+    }
+}
+
+fn render(source: ModuleSource) -> String {
+    match &source.map {
+        Some(map) => append_inline_map(&source.code, map),
+        None => source.code,
+    }
+}
+
+fn with_watch(entry: Entry) -> Response {
+    let mut res = javascript(entry.code);
+    if !entry.watch_files.is_empty() {
+        res.insert_ext(WatchFiles(entry.watch_files));
+    }
+    if !entry.watch_dirs.is_empty() {
+        res.insert_ext(WatchDirs(entry.watch_dirs));
+    }
+    res
+}
+
+fn javascript(code: String) -> Response {
+    let mut res = Response::new(StatusCode::Ok);
+    res.set_content_type("application/javascript");
+    res.set_body(code);
+    res
+}
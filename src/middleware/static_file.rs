@@ -1,32 +1,35 @@
+use crate::utils::config::Config;
+use crate::utils::error::Error;
 use crate::utils::fs;
-use std::path::Path;
+use std::sync::Arc;
 use tide::{Next, Request, Response, StatusCode};
 
 #[derive(Debug, Clone)]
 pub struct StaticFiles {
-    pub root_dir: String,
+    pub config: Arc<Config>,
 }
 
 impl StaticFiles {
-    pub fn new(root_dir: String) -> Self {
-        StaticFiles { root_dir }
+    pub fn new(config: Arc<Config>) -> Self {
+        StaticFiles { config }
     }
 }
 
 #[async_trait::async_trait]
 impl<State: Clone + Send + Sync + 'static> tide::Middleware<State> for StaticFiles {
-    async fn handle(&self, req: Request<State>, _next: Next<'_, State>) -> tide::Result {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
         let url_path = if req.url().path() == "/" {
             "index.html"
         } else {
             req.url().path().trim_start_matches('/')
         };
 
-        let static_dirs = vec![&self.root_dir, "public"];
+        // 先找项目根目录，再找 publicDir
+        let static_dirs = [self.config.root.clone(), self.config.public_dir()];
         let mut file_path = None;
 
         for dir in &static_dirs {
-            let potential_path = Path::new(dir).join(url_path);
+            let potential_path = dir.join(url_path);
             if async_std::fs::metadata(&potential_path).await.is_ok() {
                 file_path = Some(potential_path);
                 break;
@@ -39,13 +42,17 @@ impl<State: Clone + Send + Sync + 'static> tide::Middleware<State> for StaticFil
             let mime_type = mime_guess::from_path(&path)
                 .first_or_octet_stream()
                 .to_string();
-            let file = fs::read_file_bytes(&path).unwrap();
+            let file = match fs::read_file_bytes(&path) {
+                Ok(file) => file,
+                Err(e) => return Ok(Error::io(path, e).into_response(req.url().path())),
+            };
             let mut res = Response::new(StatusCode::Ok);
             res.set_content_type(mime_type.as_str());
             res.set_body(file);
             Ok(res)
         } else {
-            Ok(Response::new(StatusCode::NotFound))
+            // 找不到文件就交给后面的路由（比如 HMR 的 WebSocket），都没有匹配上才是 404
+            Ok(next.run(req).await)
         }
     }
 }
@@ -0,0 +1,110 @@
+use crate::plugins::{clean_id, extension, Command, ModuleSource, Plugin, PluginContext};
+use crate::utils::config::Config;
+use crate::utils::error::Error;
+use crate::utils::fs;
+use base64::Engine;
+use std::path::Path;
+
+/// 图片、字体、音视频这类资源的后缀名，从 js 里导入它们得到的是 url
+pub const ASSET_EXTENSIONS: [&str; 30] = [
+    "apng", "png", "jpg", "jpeg", "jfif", "pjpeg", "pjp", "gif", "svg", "ico", "webp", "avif",
+    "bmp", "tiff", "mp4", "webm", "ogg", "mp3", "wav", "flac", "aac", "opus", "mov", "m4a", "vtt",
+    "woff", "woff2", "eot", "ttf", "otf",
+];
+
+/// 导入路径上决定怎么导入资源的查询参数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetQuery {
+    /// ?raw：文件的文本内容
+    Raw,
+    /// ?inline：base64 的 data url
+    Inline,
+    /// ?url：不管是什么文件都只要 url
+    Url,
+    /// ?import：开发服务器给 js 导入的资源加上的，和 <img src> 的请求区分开
+    Import,
+}
+
+impl AssetQuery {
+    pub fn parse(id: &str) -> Option<Self> {
+        let (_, query) = id.split_once('?')?;
+        let params: Vec<&str> = query.split(['&', '#']).collect();
+        [
+            ("raw", AssetQuery::Raw),
+            ("inline", AssetQuery::Inline),
+            ("url", AssetQuery::Url),
+            ("import", AssetQuery::Import),
+        ]
+        .into_iter()
+        .find(|(name, _)| params.contains(name))
+        .map(|(_, query)| query)
+    }
+}
+
+/// js 导入时要加上 ?import 的：没有查询参数的资源和 .json，
+/// 它们也会被 <img src>、fetch() 直接请求，那时要返回文件本身
+pub fn needs_import_query(id: &str) -> bool {
+    !id.contains('?') && (ASSET_EXTENSIONS.contains(&extension(id)) || extension(id) == "json")
+}
+
+/// 由 arashi:asset 直接 load 成 js 的模块：?raw、?url、?inline 和 js 导入的资源
+pub fn is_asset_module(id: &str) -> bool {
+    match AssetQuery::parse(id) {
+        Some(AssetQuery::Import) => ASSET_EXTENSIONS.contains(&extension(id)),
+        Some(_) => true,
+        None => false,
+    }
+}
+
+/// data:image/png;base64,...
+pub fn data_url(path: &Path, content: &[u8]) -> String {
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    format!(
+        "data:{};base64,{}",
+        mime.essence_str(),
+        base64::engine::general_purpose::STANDARD.encode(content)
+    )
+}
+
+/// 开发服务器上的 url，带上 base
+fn served_url(config: &Config, id: &str) -> String {
+    config.server_url(clean_id(id))
+}
+
+/// 资源导入成导出一个字符串的 js 模块：
+/// - 直接导入资源或者 ?url：开发服务器上是文件的 url，构建时由打包器输出带 hash 的文件（小文件内联成 data url）
+/// - ?raw：文件的文本内容
+/// - ?inline：base64 的 data url
+#[derive(Debug, Default)]
+pub struct AssetPlugin;
+
+#[async_trait::async_trait]
+impl Plugin for AssetPlugin {
+    fn name(&self) -> &'static str {
+        "arashi:asset"
+    }
+
+    async fn load(&self, ctx: &PluginContext, id: &str) -> Result<Option<ModuleSource>, Error> {
+        let Some(query) = AssetQuery::parse(id).filter(|_| is_asset_module(id)) else {
+            return Ok(None);
+        };
+        let file = ctx.config.root.join(clean_id(id).trim_start_matches('/'));
+        let value = match query {
+            AssetQuery::Raw => fs::read_file_content(&file).map_err(|e| Error::io(&file, e))?,
+            AssetQuery::Inline => {
+                let content = fs::read_file_bytes(&file).map_err(|e| Error::io(&file, e))?;
+                data_url(&file, &content)
+            }
+            // 构建时要输出文件，交给打包器
+            AssetQuery::Url | AssetQuery::Import => match ctx.command {
+                Command::Serve => served_url(&ctx.config, id),
+                Command::Build => return Ok(None),
+            },
+        };
+        let code = format!(
+            "export default {};\n",
+            serde_json::to_string(&value).unwrap_or_default()
+        );
+        Ok(Some(ModuleSource::new(code)))
+    }
+}
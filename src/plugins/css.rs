@@ -0,0 +1,47 @@
+use crate::plugins::{clean_id, extension, Command, ModuleSource, Plugin, PluginContext};
+use crate::utils::css::{css_to_js, CssUrls};
+use crate::utils::error::Error;
+
+/// 导入的 css 转成插入 style 标签的 js：@import 内联进来，url() 改成能请求到的路径，
+/// *.module.css 的类名改成局部的，导出类名的映射
+#[derive(Debug, Default)]
+pub struct CssPlugin;
+
+#[async_trait::async_trait]
+impl Plugin for CssPlugin {
+    fn name(&self) -> &'static str {
+        "arashi:css"
+    }
+
+    async fn transform(
+        &self,
+        ctx: &PluginContext,
+        source: &ModuleSource,
+        id: &str,
+    ) -> Result<Option<ModuleSource>, Error> {
+        if extension(id) != "css" {
+            return Ok(None);
+        }
+        let file = ctx.config.root.join(clean_id(id).trim_start_matches('/'));
+        // 开发服务器里的 style 标签可以热更新；
+        // 构建的时候 css 抽出来交给打包器合并到 chunk 的 .css 文件里，url() 引用的文件输出成带 hash 的文件
+        let (urls, hot) = match ctx.command {
+            Command::Serve => (CssUrls::Serve, true),
+            Command::Build => (CssUrls::Extract, false),
+        };
+        let output = css_to_js(&source.code, &file, &ctx.config, urls, hot).map_err(|e| {
+            Error::Transform {
+                plugin: self.name(),
+                file: e.file,
+                error: e.error,
+            }
+        })?;
+        Ok(Some(ModuleSource {
+            code: output.code,
+            map: None,
+            watch_files: output.watch_files,
+            watch_dirs: Vec::new(),
+            css: output.css,
+        }))
+    }
+}
@@ -0,0 +1,129 @@
+use crate::plugins::{Command, HotUpdate, HotUpdateContext, ModuleSource, Plugin, PluginContext};
+use crate::utils::config::Config;
+use crate::utils::error::Error;
+use crate::utils::hmr::has_react_refresh;
+use crate::utils::import_analysis::is_bare_import;
+use crate::utils::prebuild::DepCache;
+use crate::utils::resolver::{ResolveKind, Resolver};
+use crate::utils::scanner::scan_deps;
+use crate::utils::watcher::{WatchEvent, WatchListener};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// 第三方依赖的导入改写成 /@modules/xxx，请求的时候返回预构建好的文件
+#[derive(Clone)]
+pub struct DepsPlugin {
+    config: Arc<Config>,
+    dep_cache: Arc<RwLock<DepCache>>,
+}
+
+impl DepsPlugin {
+    pub async fn new(config: Arc<Config>) -> Result<Self, Error> {
+        let dep_cache = Arc::new(RwLock::new(DepCache::new(config.clone()).await?));
+        Ok(Self { config, dep_cache })
+    }
+
+    /// 扫描 index.html 能走到的源码，把用到的第三方依赖一次性预构建，
+    /// 再加上配置里的 optimizeDeps.include，去掉 optimizeDeps.exclude。返回是不是真的重新构建了
+    pub async fn optimize_deps(&self) -> bool {
+        let mut deps = scan_deps(&self.config);
+        // react-refresh 的 runtime 是注入到 html 里的，扫描不到
+        if has_react_refresh(&self.config.root) {
+            deps.insert("react-refresh/runtime".to_string());
+        }
+        let optimize_deps = &self.config.optimize_deps;
+        deps.extend(optimize_deps.include.iter().cloned());
+        deps.retain(|dep| !optimize_deps.exclude.contains(dep));
+        match self.dep_cache.write().await.prebundle(deps).await {
+            Ok(rebuilt) => rebuilt,
+            Err(e) => {
+                println!("[deps] 预构建失败: {}", e);
+                false
+            }
+        }
+    }
+
+    /// /@modules/xxx：返回预构建好的依赖，启动时没有预构建的话现在单独构建
+    async fn load_dependency(&self, module_name: &str) -> Result<String, Error> {
+        // 启动时预构建好的依赖和它们共享的 chunk
+        let prebuilt = self
+            .dep_cache
+            .read()
+            .await
+            .get_prebuilt_path(module_name)
+            .await;
+        let file = match prebuilt {
+            Some(prebuilt) => prebuilt,
+            None => {
+                let pkg_path = Resolver::from_config(&self.config)
+                    .resolve(module_name, &self.config.root, ResolveKind::Import)
+                    .ok_or_else(|| Error::Resolve {
+                        specifier: module_name.to_string(),
+                        importer: self.config.root.display().to_string(),
+                    })?;
+                DepCache::get_or_build(&self.dep_cache, module_name, &pkg_path).await?
+            }
+        };
+        async_std::fs::read_to_string(&file)
+            .await
+            .map_err(|e| Error::io(&file, e))
+    }
+}
+
+#[async_trait::async_trait]
+impl Plugin for DepsPlugin {
+    fn name(&self) -> &'static str {
+        "arashi:deps"
+    }
+
+    async fn resolve_id(
+        &self,
+        ctx: &PluginContext,
+        specifier: &str,
+        _importer: &str,
+    ) -> Result<Option<String>, Error> {
+        // 构建的时候依赖和源码打包在一起，不需要改写
+        if ctx.command == Command::Build || !is_bare_import(specifier) {
+            return Ok(None);
+        }
+        Ok(Some(format!("/@modules/{}", specifier)))
+    }
+
+    async fn load(&self, _ctx: &PluginContext, id: &str) -> Result<Option<ModuleSource>, Error> {
+        match id.strip_prefix("/@modules/") {
+            Some(module_name) => Ok(Some(ModuleSource::new(
+                self.load_dependency(module_name).await?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    async fn handle_hot_update(
+        &self,
+        ctx: &PluginContext,
+        update: &HotUpdateContext<'_>,
+    ) -> Option<HotUpdate> {
+        if !update.event.is_dependency_change(&ctx.config) {
+            return None;
+        }
+        // 同一批变化里前面的事件已经重新构建过了
+        if !self.dep_cache.read().await.is_stale() {
+            return Some(HotUpdate::Handled);
+        }
+        // 重新构建之后已经加载的第三方模块都是旧的，只能整页刷新；构建失败的话页面先不动
+        match self.optimize_deps().await {
+            true => Some(HotUpdate::FullReload),
+            false => Some(HotUpdate::Handled),
+        }
+    }
+}
+
+/// 在 HMR 之前订阅：一批变化先全部标记完，HMR 处理第一个依赖变化的时候只重新构建一次
+#[async_trait::async_trait]
+impl WatchListener for DepsPlugin {
+    async fn on_change(&self, event: &WatchEvent) {
+        if event.is_dependency_change(&self.config) {
+            self.dep_cache.write().await.mark_stale();
+        }
+    }
+}
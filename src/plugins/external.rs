@@ -0,0 +1,356 @@
+use crate::plugins::{clean_id, extension, Command, Enforce, ModuleSource, Plugin, PluginContext};
+use crate::utils::config::{Config, ExternalPluginConfig};
+use crate::utils::error::Error;
+use crate::utils::transform::TransformError;
+use oxc_sourcemap::SourceMap;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// 一行里最多合并多少个请求
+const MAX_BATCH: usize = 64;
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<Value, RpcError>>>>>;
+
+/// JSON-RPC 的 error，data 里可以带上出错的行列号和源码片段
+#[derive(Debug, Deserialize)]
+struct RpcError {
+    message: String,
+    #[serde(default)]
+    data: Option<RpcErrorData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcErrorData {
+    line: Option<usize>,
+    column: Option<usize>,
+    frame: Option<String>,
+}
+
+impl RpcError {
+    fn new(message: impl Into<String>) -> Self {
+        RpcError {
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse {
+    id: Option<u64>,
+    #[serde(default)]
+    result: Value,
+    error: Option<RpcError>,
+}
+
+/// initialize 的返回值：插件实现了哪些钩子，load / transform 只处理哪些后缀名
+#[derive(Debug, Default, Deserialize)]
+struct InitializeResult {
+    #[serde(default)]
+    hooks: Vec<String>,
+    #[serde(default)]
+    extensions: Vec<String>,
+}
+
+/// load / transform 的返回值，map 可以是 source map 对象或者它的 JSON 字符串
+#[derive(Debug, Deserialize)]
+struct SourceResult {
+    code: String,
+    #[serde(default)]
+    map: Option<Value>,
+}
+
+/// 外部插件：一个子进程，通过 stdin / stdout 按行收发 JSON-RPC 2.0 消息，协议见 plugin-protocol.md
+///
+/// 同时发出的请求会合并成一个 batch（JSON 数组）写成一行，每个请求单独计算超时
+pub struct ExternalPlugin {
+    name: &'static str,
+    enforce: Enforce,
+    timeout: Duration,
+    hooks: Vec<String>,
+    extensions: Vec<String>,
+    next_id: AtomicU64,
+    pending: Pending,
+    sender: mpsc::Sender<Value>,
+    alive: Arc<AtomicBool>,
+    child: Mutex<Child>,
+}
+
+impl ExternalPlugin {
+    /// 启动插件进程，等它回复 initialize 之后才算启动完成
+    pub async fn start(
+        config: &Config,
+        plugin: &ExternalPluginConfig,
+        command: Command,
+    ) -> Result<Self, Error> {
+        // 插件在整个进程里只会启动一次，名字直接留着
+        let name: &'static str = Box::leak(plugin.name.clone().into_boxed_str());
+        let mut child = std::process::Command::new(&plugin.command)
+            .args(&plugin.args)
+            .current_dir(&config.root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| {
+                Error::plugin_error(name, format!("无法启动 {}: {}", plugin.command, e))
+            })?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(Error::plugin_error(name, "无法连接插件的 stdin / stdout"));
+        };
+
+        let (sender, receiver) = mpsc::channel();
+        let pending = Pending::default();
+        let alive = Arc::new(AtomicBool::new(true));
+        std::thread::spawn(move || write_batches(stdin, receiver));
+        {
+            let pending = pending.clone();
+            let alive = alive.clone();
+            std::thread::spawn(move || read_responses(name, stdout, pending, alive));
+        }
+
+        let mut external = ExternalPlugin {
+            name,
+            enforce: plugin.enforce,
+            timeout: Duration::from_millis(plugin.timeout),
+            hooks: Vec::new(),
+            extensions: Vec::new(),
+            next_id: AtomicU64::new(1),
+            pending,
+            sender,
+            alive,
+            child: Mutex::new(child),
+        };
+        let params = json!({
+            "root": config.root,
+            "command": match command {
+                Command::Serve => "serve",
+                Command::Build => "build",
+            },
+            "version": env!("CARGO_PKG_VERSION"),
+        });
+        let init: InitializeResult = external
+            .call("initialize", params)
+            .await
+            .and_then(|result| {
+                serde_json::from_value(result).map_err(|e| RpcError::new(e.to_string()))
+            })
+            .map_err(|e| Error::plugin_error(name, format!("initialize 失败: {}", e.message)))?;
+        external.hooks = init.hooks;
+        external.extensions = init.extensions;
+        println!(
+            "[plugins] {} 已启动，钩子: {}",
+            name,
+            external.hooks.join(", ")
+        );
+        Ok(external)
+    }
+
+    /// 发一个请求并等待结果，超时或者插件进程退出都算失败
+    async fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        if !self.alive.load(Ordering::SeqCst) {
+            return Err(RpcError::new("插件进程已经退出"));
+        }
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        if self.sender.send(message).is_err() {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(RpcError::new("插件进程已经退出"));
+        }
+        match async_std::future::timeout(self.timeout, rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(RpcError::new("插件进程已经退出")),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                Err(RpcError::new(format!(
+                    "{} 超时（{}ms）",
+                    method,
+                    self.timeout.as_millis()
+                )))
+            }
+        }
+    }
+
+    fn has_hook(&self, hook: &str) -> bool {
+        self.hooks.iter().any(|h| h == hook)
+    }
+
+    /// 没有声明 extensions 的话所有模块都交给插件
+    fn handles(&self, id: &str) -> bool {
+        self.extensions.is_empty() || self.extensions.iter().any(|ext| ext == extension(id))
+    }
+
+    fn to_source(&self, result: Value) -> Result<Option<ModuleSource>, Error> {
+        if result.is_null() {
+            return Ok(None);
+        }
+        let result: SourceResult = serde_json::from_value(result)
+            .map_err(|e| Error::plugin_error(self.name, format!("返回值格式不对: {}", e)))?;
+        let map = match result.map {
+            None | Some(Value::Null) => None,
+            Some(Value::String(json)) => Some(parse_map(self.name, &json)?),
+            Some(map) => Some(parse_map(self.name, &map.to_string())?),
+        };
+        Ok(Some(ModuleSource {
+            code: result.code,
+            map,
+            watch_files: Vec::new(),
+            watch_dirs: Vec::new(),
+            css: None,
+        }))
+    }
+}
+
+fn parse_map(plugin: &'static str, json: &str) -> Result<SourceMap, Error> {
+    SourceMap::from_json_string(json)
+        .map_err(|e| Error::plugin_error(plugin, format!("source map 格式不对: {:?}", e)))
+}
+
+/// 把排队的请求一次写出去，同时有多个的话合并成一个 batch
+fn write_batches(mut stdin: ChildStdin, receiver: mpsc::Receiver<Value>) {
+    while let Ok(first) = receiver.recv() {
+        let mut batch = vec![first];
+        while batch.len() < MAX_BATCH {
+            match receiver.try_recv() {
+                Ok(message) => batch.push(message),
+                Err(_) => break,
+            }
+        }
+        let line = match batch.len() {
+            1 => batch[0].to_string(),
+            _ => Value::Array(batch).to_string(),
+        };
+        if writeln!(stdin, "{}", line)
+            .and_then(|_| stdin.flush())
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// 按 id 把结果交给等待的请求，插件进程退出之后还在等的请求都失败
+fn read_responses(name: &str, stdout: ChildStdout, pending: Pending, alive: Arc<AtomicBool>) {
+    for line in BufReader::new(stdout).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let responses: Vec<RpcResponse> = match serde_json::from_str::<Value>(&line) {
+            Ok(Value::Array(items)) => items
+                .into_iter()
+                .filter_map(|item| serde_json::from_value(item).ok())
+                .collect(),
+            Ok(item) => serde_json::from_value(item).ok().into_iter().collect(),
+            // 不是 JSON 的输出当成插件的日志
+            Err(_) => {
+                println!("[plugins] {}: {}", name, line);
+                continue;
+            }
+        };
+        for response in responses {
+            let Some(id) = response.id else {
+                continue;
+            };
+            if let Some(sender) = pending.lock().unwrap().remove(&id) {
+                let _ = sender.send(match response.error {
+                    Some(error) => Err(error),
+                    None => Ok(response.result),
+                });
+            }
+        }
+    }
+    // 正常结束的时候 drop 已经把 alive 设成了 false，不用提示
+    if alive.swap(false, Ordering::SeqCst) {
+        println!("[plugins] {} 进程已经退出", name);
+    }
+    for (_, sender) in pending.lock().unwrap().drain() {
+        let _ = sender.send(Err(RpcError::new("插件进程已经退出")));
+    }
+}
+
+impl Drop for ExternalPlugin {
+    fn drop(&mut self) {
+        self.alive.store(false, Ordering::SeqCst);
+        if let Ok(child) = self.child.get_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Plugin for ExternalPlugin {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn enforce(&self) -> Enforce {
+        self.enforce
+    }
+
+    async fn resolve_id(
+        &self,
+        _ctx: &PluginContext,
+        specifier: &str,
+        importer: &str,
+    ) -> Result<Option<String>, Error> {
+        if !self.has_hook("resolveId") {
+            return Ok(None);
+        }
+        let params = json!({ "specifier": specifier, "importer": importer });
+        match self.call("resolveId", params).await {
+            Ok(Value::String(id)) => Ok(Some(id)),
+            Ok(_) => Ok(None),
+            Err(e) => Err(Error::plugin_error(self.name, e.message)),
+        }
+    }
+
+    async fn load(&self, _ctx: &PluginContext, id: &str) -> Result<Option<ModuleSource>, Error> {
+        if !self.has_hook("load") || !self.handles(id) {
+            return Ok(None);
+        }
+        let result = self
+            .call("load", json!({ "id": id }))
+            .await
+            .map_err(|e| Error::plugin_error(self.name, e.message))?;
+        self.to_source(result)
+    }
+
+    async fn transform(
+        &self,
+        ctx: &PluginContext,
+        source: &ModuleSource,
+        id: &str,
+    ) -> Result<Option<ModuleSource>, Error> {
+        if !self.has_hook("transform") || !self.handles(id) {
+            return Ok(None);
+        }
+        let params = json!({ "code": source.code, "id": id });
+        let result = self.call("transform", params).await.map_err(|e| {
+            let data = e.data;
+            Error::Transform {
+                plugin: self.name,
+                file: ctx.config.root.join(clean_id(id).trim_start_matches('/')),
+                error: TransformError {
+                    message: e.message,
+                    line: data.as_ref().and_then(|d| d.line),
+                    column: data.as_ref().and_then(|d| d.column),
+                    frame: data.and_then(|d| d.frame),
+                },
+            }
+        })?;
+        self.to_source(result)
+    }
+}
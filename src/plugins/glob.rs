@@ -0,0 +1,50 @@
+use crate::plugins::{clean_id, ModuleSource, Plugin, PluginContext};
+use crate::utils::error::Error;
+use crate::utils::glob::expand_glob_imports;
+use crate::utils::transform::is_js_or_ts_file;
+
+/// import.meta.glob("./pages/*.tsx") 展开成 { 路径: () => import(路径) } 这样的对象，
+/// 排在 arashi:typescript 后面，拿到的都是 js。匹配的目录记在 watch_dirs 里，增删文件之后重新展开
+#[derive(Debug, Default)]
+pub struct GlobPlugin;
+
+#[async_trait::async_trait]
+impl Plugin for GlobPlugin {
+    fn name(&self) -> &'static str {
+        "arashi:glob"
+    }
+
+    async fn transform(
+        &self,
+        ctx: &PluginContext,
+        source: &ModuleSource,
+        id: &str,
+    ) -> Result<Option<ModuleSource>, Error> {
+        let url = clean_id(id);
+        if !is_js_or_ts_file(url) && !url.ends_with(".mjs") {
+            return Ok(None);
+        }
+        let expansion = expand_glob_imports(&source.code, url, &ctx.config).map_err(|error| {
+            Error::Transform {
+                plugin: self.name(),
+                file: ctx.config.root.join(url.trim_start_matches('/')),
+                error,
+            }
+        })?;
+        let Some(mut expansion) = expansion else {
+            return Ok(None);
+        };
+        let code = expansion.edits.apply(&source.code);
+        let map = source
+            .map
+            .as_ref()
+            .map(|map| expansion.edits.remap(map, &source.code, &code));
+        Ok(Some(ModuleSource {
+            code,
+            map,
+            watch_files: Vec::new(),
+            watch_dirs: expansion.dirs,
+            css: None,
+        }))
+    }
+}
@@ -0,0 +1,130 @@
+use crate::plugins::asset::AssetQuery;
+use crate::plugins::{clean_id, extension, Command, ModuleSource, Plugin, PluginContext};
+use crate::utils::error::Error;
+use crate::utils::transform::{code_frame, TransformError};
+use serde_json::Value;
+
+/// 不能用作 const 变量名的保留字，这些键只能从 default 里拿
+const RESERVED_WORDS: [&str; 48] = [
+    "arguments",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "eval",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+];
+
+/// 导入的 .json 转成 esm：默认导出整个值，顶层的键是合法标识符的话还有同名的具名导出。
+/// 每个键是一个单独的 const，default 引用它们，值不会在产物里重复出现
+/// （构建时模块包在 __define 里，没有 tree-shaking，用不到的键也会留在产物里）
+///
+/// 开发服务器上只转换 js 导入的（带 ?import），fetch("/data.json") 还是原样返回
+#[derive(Debug, Default)]
+pub struct JsonPlugin;
+
+#[async_trait::async_trait]
+impl Plugin for JsonPlugin {
+    fn name(&self) -> &'static str {
+        "arashi:json"
+    }
+
+    async fn transform(
+        &self,
+        ctx: &PluginContext,
+        source: &ModuleSource,
+        id: &str,
+    ) -> Result<Option<ModuleSource>, Error> {
+        if extension(id) != "json"
+            || ctx.command == Command::Serve && AssetQuery::parse(id) != Some(AssetQuery::Import)
+        {
+            return Ok(None);
+        }
+        let value: Value = serde_json::from_str(&source.code).map_err(|e| Error::Transform {
+            plugin: self.name(),
+            file: ctx.config.root.join(clean_id(id).trim_start_matches('/')),
+            error: TransformError {
+                message: e.to_string(),
+                line: Some(e.line()),
+                column: Some(e.column()),
+                frame: Some(code_frame(&source.code, e.line(), e.column())),
+            },
+        })?;
+        Ok(Some(ModuleSource::new(json_to_esm(&value))))
+    }
+}
+
+fn json_to_esm(value: &Value) -> String {
+    let Value::Object(object) = value else {
+        return format!("export default {};\n", value);
+    };
+    let mut code = String::new();
+    let mut properties = String::new();
+    for (key, value) in object {
+        if !is_export_name(key) {
+            properties.push_str(&format!("  {}: {},\n", Value::from(key.as_str()), value));
+            continue;
+        }
+        code.push_str(&format!("export const {} = {};\n", key, value));
+        // 压缩的时候简写会被展开，__proto__: x 是设置原型，要用计算属性名
+        match key.as_str() {
+            "__proto__" => properties.push_str("  [\"__proto__\"]: __proto__,\n"),
+            _ => properties.push_str(&format!("  {},\n", key)),
+        }
+    }
+    code.push_str(&format!("export default {{\n{}}};\n", properties));
+    code
+}
+
+/// 能写成 export const xxx 的键
+fn is_export_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    let valid_start = chars
+        .next()
+        .map(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        .unwrap_or(false);
+    valid_start
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+        && !RESERVED_WORDS.contains(&name)
+}
@@ -0,0 +1,289 @@
+pub mod asset;
+pub mod css;
+pub mod deps;
+pub mod external;
+pub mod glob;
+pub mod json;
+pub mod typescript;
+
+use crate::plugins::asset::is_asset_module;
+use crate::plugins::external::ExternalPlugin;
+use crate::utils::config::Config;
+use crate::utils::error::Error;
+use crate::utils::watcher::WatchEvent;
+use oxc_sourcemap::SourceMap;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// 插件在开发服务器里运行还是在 arashi build 里运行
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Serve,
+    Build,
+}
+
+/// 插件相对内置插件的位置：pre 在内置插件之前，normal 和 post 在之后
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Enforce {
+    Pre,
+    #[default]
+    Normal,
+    Post,
+}
+
+/// 每个钩子都能拿到的上下文
+#[derive(Debug, Clone)]
+pub struct PluginContext {
+    pub config: Arc<Config>,
+    pub command: Command,
+}
+
+/// load / transform 的结果，map 是 code 对应的 source map，
+/// watch_files 是模块自己的文件以外、变化之后结果也会变的文件，比如 css 里 @import 的文件，
+/// watch_dirs 是 import.meta.glob 匹配的目录，里面增删文件之后结果也会变，
+/// css 是构建时从模块里抽出来的样式，打包器把它们合并到 chunk 对应的 .css 文件里
+#[derive(Debug, Clone)]
+pub struct ModuleSource {
+    pub code: String,
+    pub map: Option<SourceMap>,
+    pub watch_files: Vec<PathBuf>,
+    pub watch_dirs: Vec<PathBuf>,
+    pub css: Option<String>,
+}
+
+impl ModuleSource {
+    pub fn new(code: impl Into<String>) -> Self {
+        ModuleSource {
+            code: code.into(),
+            map: None,
+            watch_files: Vec::new(),
+            watch_dirs: Vec::new(),
+            css: None,
+        }
+    }
+}
+
+/// 文件变化时传给 handle_hot_update 的信息，modules 是模块图里对应这个文件的模块
+#[derive(Debug)]
+pub struct HotUpdateContext<'a> {
+    pub event: &'a WatchEvent,
+    pub modules: Vec<String>,
+}
+
+/// handle_hot_update 的处理结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HotUpdate {
+    /// 按这些模块走正常的 HMR 流程
+    Modules(Vec<String>),
+    /// 整页刷新
+    FullReload,
+    /// 插件自己处理好了，不用再通知浏览器
+    Handled,
+}
+
+/// 插件，钩子按插件的顺序调用，都有默认实现，只需要实现用到的：
+///
+/// - `resolve_id`：改写导入路径，第一个返回 Some 的插件生效
+/// - `load`：返回模块的内容，第一个返回 Some 的插件生效，都没有的话读文件
+/// - `transform`：依次转换模块的内容，上一个插件的结果是下一个插件的输入
+/// - `config_resolved`：配置加载完之后调用一次
+/// - `handle_hot_update`：文件变化时调用，可以改变要更新的模块
+/// - `transform_index_html`：依次转换 index.html
+///
+/// 开发服务器里模块的 id 是请求的路径，比如 /src/App.tsx；构建的时候是 / 加上相对项目根目录的路径
+#[async_trait::async_trait]
+pub trait Plugin: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn enforce(&self) -> Enforce {
+        Enforce::Normal
+    }
+
+    fn config_resolved(&mut self, _ctx: &PluginContext) {}
+
+    async fn resolve_id(
+        &self,
+        _ctx: &PluginContext,
+        _specifier: &str,
+        _importer: &str,
+    ) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+
+    async fn load(&self, _ctx: &PluginContext, _id: &str) -> Result<Option<ModuleSource>, Error> {
+        Ok(None)
+    }
+
+    async fn transform(
+        &self,
+        _ctx: &PluginContext,
+        _source: &ModuleSource,
+        _id: &str,
+    ) -> Result<Option<ModuleSource>, Error> {
+        Ok(None)
+    }
+
+    async fn handle_hot_update(
+        &self,
+        _ctx: &PluginContext,
+        _update: &HotUpdateContext<'_>,
+    ) -> Option<HotUpdate> {
+        None
+    }
+
+    fn transform_index_html(&self, _ctx: &PluginContext, _html: &str) -> Option<String> {
+        None
+    }
+}
+
+/// 按顺序保存所有插件，负责调用它们的钩子
+pub struct PluginContainer {
+    ctx: PluginContext,
+    plugins: Vec<Box<dyn Plugin>>,
+}
+
+impl PluginContainer {
+    /// 内置插件在前，用户插件按 enforce 排到内置插件的前面或者后面
+    pub fn new(
+        config: Arc<Config>,
+        command: Command,
+        builtin: Vec<Box<dyn Plugin>>,
+        user: Vec<Box<dyn Plugin>>,
+    ) -> Self {
+        let ctx = PluginContext { config, command };
+        let (pre, rest): (Vec<_>, Vec<_>) =
+            user.into_iter().partition(|p| p.enforce() == Enforce::Pre);
+        let mut plugins: Vec<Box<dyn Plugin>> = pre.into_iter().chain(builtin).collect();
+        let mut rest = rest;
+        rest.sort_by_key(|p| p.enforce());
+        plugins.extend(rest);
+        for plugin in &mut plugins {
+            plugin.config_resolved(&ctx);
+        }
+        PluginContainer { ctx, plugins }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.ctx.config
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.plugins.iter().map(|p| p.name()).collect()
+    }
+
+    pub async fn resolve_id(
+        &self,
+        specifier: &str,
+        importer: &str,
+    ) -> Result<Option<String>, Error> {
+        for plugin in &self.plugins {
+            if let Some(id) = plugin.resolve_id(&self.ctx, specifier, importer).await? {
+                return Ok(Some(id));
+            }
+        }
+        Ok(None)
+    }
+
+    pub async fn load(&self, id: &str) -> Result<Option<ModuleSource>, Error> {
+        for plugin in &self.plugins {
+            if let Some(source) = plugin.load(&self.ctx, id).await? {
+                return Ok(Some(source));
+            }
+        }
+        Ok(None)
+    }
+
+    /// 没有插件转换过的话返回 None。
+    /// 转换之后没有带 source map 的插件会让之前的 source map 失效。
+    /// ?raw、?url、?inline 这类导入已经被 arashi:asset 转成了 js，不再按后缀名转换
+    pub async fn transform(
+        &self,
+        source: ModuleSource,
+        id: &str,
+    ) -> Result<Option<ModuleSource>, Error> {
+        if is_asset_module(id) {
+            return Ok(None);
+        }
+        let mut current = source;
+        let mut transformed = false;
+        for plugin in &self.plugins {
+            if let Some(mut next) = plugin.transform(&self.ctx, &current, id).await? {
+                // 前面的插件依赖的文件，最终的结果也依赖
+                for file in current.watch_files.drain(..) {
+                    if !next.watch_files.contains(&file) {
+                        next.watch_files.push(file);
+                    }
+                }
+                for dir in current.watch_dirs.drain(..) {
+                    if !next.watch_dirs.contains(&dir) {
+                        next.watch_dirs.push(dir);
+                    }
+                }
+                // 后面的插件只改了 js 的话，抽出来的 css 还是前面的
+                if next.css.is_none() {
+                    next.css = current.css.take();
+                }
+                current = next;
+                transformed = true;
+            }
+        }
+        Ok(transformed.then_some(current))
+    }
+
+    pub async fn handle_hot_update(&self, event: &WatchEvent, modules: Vec<String>) -> HotUpdate {
+        let mut update = HotUpdateContext { event, modules };
+        for plugin in &self.plugins {
+            match plugin.handle_hot_update(&self.ctx, &update).await {
+                Some(HotUpdate::Modules(modules)) => update.modules = modules,
+                Some(other) => return other,
+                None => {}
+            }
+        }
+        HotUpdate::Modules(update.modules)
+    }
+
+    pub fn transform_index_html(&self, html: String) -> String {
+        self.plugins.iter().fold(html, |html, plugin| {
+            plugin
+                .transform_index_html(&self.ctx, &html)
+                .unwrap_or(html)
+        })
+    }
+}
+
+/// 在这里注册项目自己的插件，比如支持新的文件类型：
+///
+/// ```ignore
+/// vec![Box::new(MyYamlPlugin)]
+/// ```
+pub fn user_plugins() -> Vec<Box<dyn Plugin>> {
+    Vec::new()
+}
+
+/// 项目的插件：user_plugins() 里注册的，加上配置文件里的外部插件
+pub async fn project_plugins(
+    config: &Config,
+    command: Command,
+) -> Result<Vec<Box<dyn Plugin>>, Error> {
+    let mut plugins = user_plugins();
+    for plugin in &config.plugins {
+        plugins.push(Box::new(
+            ExternalPlugin::start(config, plugin, command).await?,
+        ));
+    }
+    Ok(plugins)
+}
+
+/// 去掉 ?t=123 这类查询参数
+pub fn clean_id(id: &str) -> &str {
+    id.split(['?', '#']).next().unwrap_or(id)
+}
+
+/// id 的后缀名，不带 .
+pub fn extension(id: &str) -> &str {
+    let id = clean_id(id);
+    let name = id.rsplit('/').next().unwrap_or(id);
+    name.rsplit_once('.').map(|(_, ext)| ext).unwrap_or("")
+}
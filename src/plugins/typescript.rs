@@ -0,0 +1,79 @@
+use crate::plugins::{clean_id, extension, Command, ModuleSource, Plugin, PluginContext};
+use crate::utils::config::SourcemapMode;
+use crate::utils::error::Error;
+use crate::utils::hmr::{has_react_refresh, wrap_react_refresh};
+use crate::utils::sourcemap::CodeEdits;
+use crate::utils::transform::transform_typescript;
+use std::path::{Path, PathBuf};
+
+/// ts / tsx / jsx 转成 js，开发时给 React 组件加上 react-refresh 的注册代码
+#[derive(Debug, Default)]
+pub struct TypescriptPlugin {
+    react_refresh: bool,
+    sourcemap: bool,
+}
+
+impl TypescriptPlugin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl Plugin for TypescriptPlugin {
+    fn name(&self) -> &'static str {
+        "arashi:typescript"
+    }
+
+    fn config_resolved(&mut self, ctx: &PluginContext) {
+        let serve = ctx.command == Command::Serve;
+        self.react_refresh = serve && has_react_refresh(&ctx.config.root);
+        self.sourcemap = serve && ctx.config.server.sourcemap != SourcemapMode::Disabled;
+    }
+
+    async fn transform(
+        &self,
+        ctx: &PluginContext,
+        source: &ModuleSource,
+        id: &str,
+    ) -> Result<Option<ModuleSource>, Error> {
+        let ext = extension(id);
+        if !matches!(ext, "ts" | "tsx" | "jsx" | "mts" | "cts") {
+            return Ok(None);
+        }
+        let url = clean_id(id);
+        // map 里的源文件名用文件名，浏览器按模块的 url 找到它
+        let source_map_path = self
+            .sourcemap
+            .then(|| Path::new(url.rsplit('/').next().unwrap_or(url)));
+        let output = transform_typescript(
+            &source.code,
+            ext != "ts",
+            self.react_refresh,
+            &ctx.config,
+            source_map_path,
+        )
+        .map_err(|error| Error::Transform {
+            plugin: self.name(),
+            file: ctx
+                .config
+                .root
+                .join(PathBuf::from(url.trim_start_matches('/'))),
+            error,
+        })?;
+
+        let mut edits = CodeEdits::new();
+        if self.react_refresh {
+            wrap_react_refresh(&mut edits, &output.code, url, &ctx.config);
+        }
+        let code = edits.apply(&output.code);
+        let map = output.map.map(|map| edits.remap(&map, &output.code, &code));
+        Ok(Some(ModuleSource {
+            code,
+            map,
+            watch_files: Vec::new(),
+            watch_dirs: Vec::new(),
+            css: None,
+        }))
+    }
+}
@@ -0,0 +1,396 @@
+use crate::plugins::asset::data_url;
+use crate::utils::bundler::{self, hashed_file_name, AppBundle, BundleOutput};
+use crate::utils::config::Config;
+use crate::utils::css::{asset_placeholder, extract_stylesheet};
+use crate::utils::error::Error;
+use crate::utils::fs;
+use crate::utils::import_analysis::analyze_imports;
+use crate::utils::minify::{format_size, minify, minify_css};
+use crate::utils::resolver::{ResolveKind, Resolver};
+use crate::utils::scanner::{scan_html_scripts, scan_html_stylesheets, HtmlScript};
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::time::Instant;
+
+/// 生产构建：从 index.html 出发，把源码和依赖打包成带 hash 的文件放到 outDir，
+/// 再把 html 里的路径改成打包后的文件。任何一个模块转换或者解析失败都会返回错误
+pub fn build(config: &Config) -> Result<(), Error> {
+    let started = Instant::now();
+    // 没有特意配置的话，生产构建的 NODE_ENV 是 production
+    let mut config = config.clone();
+    config
+        .define
+        .entry("process.env.NODE_ENV".to_string())
+        .or_insert_with(|| "\"production\"".into());
+    let config = &config;
+
+    let html_path = config.root.join("index.html");
+    let mut html = fs::read_file_content(&html_path).map_err(|e| Error::io(&html_path, e))?;
+
+    let entries = html_entries(config, &html)?;
+    if entries.is_empty() {
+        return Err(Error::build(
+            &html_path,
+            "没有找到 <script type=\"module\" src=\"...\">",
+        ));
+    }
+    println!(
+        "[build] 入口: {}",
+        entries
+            .iter()
+            .map(|(_, name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let bundle_entries: Vec<(String, PathBuf)> = entries
+        .iter()
+        .map(|(_, name, path)| (name.clone(), path.clone()))
+        .collect();
+    let AppBundle {
+        outputs,
+        assets,
+        css,
+    } = bundler::bundle_app(config, &bundle_entries)?;
+    // 压缩要在算 hash 之前，文件名里的 hash 是最终内容的 hash
+    let mut original_sizes = HashMap::new();
+    let outputs = if config.build.minify {
+        let mut minified = Vec::new();
+        for output in outputs {
+            let code = if output.file_name.ends_with(".css") {
+                minify_css(&output.code)
+            } else {
+                minify(&output.code)
+            }
+            .map_err(|e| Error::build(&output.file_name, format!("压缩失败: {}", e)))?;
+            original_sizes.insert(output.file_name.clone(), output.code.len());
+            minified.push(BundleOutput {
+                file_name: output.file_name,
+                code,
+            });
+        }
+        minified
+    } else {
+        outputs
+    };
+    let (files, renamed) = hash_outputs(outputs);
+    let mut original_sizes: HashMap<String, usize> = renamed
+        .iter()
+        .filter_map(|(from, to)| Some((to.clone(), *original_sizes.get(from)?)))
+        .collect();
+
+    let out_dir = config.out_dir();
+    let assets_dir = out_dir.join(&config.build.assets_dir);
+    prepare_out_dir(config, &out_dir)?;
+    std::fs::create_dir_all(&assets_dir).map_err(|e| Error::io(&assets_dir, e))?;
+
+    let mut emitted = Vec::new();
+    for file in &files {
+        let path = assets_dir.join(&file.file_name);
+        std::fs::write(&path, &file.code).map_err(|e| Error::io(&path, e))?;
+        emitted.push((file.file_name.clone(), file.code.len()));
+    }
+    for (source, file_name) in &assets {
+        let size =
+            std::fs::copy(source, assets_dir.join(file_name)).map_err(|e| Error::io(source, e))?;
+        emitted.push((file_name.clone(), size as usize));
+    }
+
+    // html 里的入口换成打包后的文件，入口用到的 css 按导入的顺序加到 <head> 里
+    let mut stylesheets = Vec::new();
+    for (src, name, _) in &entries {
+        let file_name = &renamed[&format!("{}.js", name)];
+        html = replace_attr(&html, "src", src, &config.asset_url(file_name));
+        for css_file in css.get(name).into_iter().flatten() {
+            let href = config.asset_url(&renamed[css_file]);
+            if !stylesheets.contains(&href) {
+                stylesheets.push(href);
+            }
+        }
+    }
+    html = inject_stylesheets(&html, &stylesheets);
+    // <link rel="stylesheet"> 引用的源码里的 css 和 js 导入的 css 一样编译：
+    // 内联 @import，url() 引用的文件输出成带 hash 的文件（小文件内联），再压缩
+    for href in scan_html_stylesheets(&html) {
+        let Some(source) = local_file(config, &href) else {
+            continue;
+        };
+        let content = fs::read_file_content(&source).map_err(|e| Error::io(&source, e))?;
+        let (mut css, urls) =
+            extract_stylesheet(&content, &source, config).map_err(|e| Error::Transform {
+                plugin: "arashi:css",
+                file: e.file,
+                error: e.error,
+            })?;
+        for url in urls {
+            let asset = config.root.join(url.trim_start_matches('/'));
+            let bytes = fs::read_file_bytes(&asset).map_err(|e| Error::io(&asset, e))?;
+            let asset_url = if bytes.len() < config.build.assets_inline_limit {
+                data_url(&asset, &bytes)
+            } else {
+                let file_name = hashed_file_name(&asset, &bytes);
+                if !emitted.iter().any(|(name, _)| *name == file_name) {
+                    let path = assets_dir.join(&file_name);
+                    std::fs::write(&path, &bytes).map_err(|e| Error::io(&path, e))?;
+                    emitted.push((file_name.clone(), bytes.len()));
+                }
+                config.asset_url(&file_name)
+            };
+            css = css.replace(&asset_placeholder(&url), &asset_url);
+        }
+        let original_size = css.len();
+        if config.build.minify {
+            css =
+                minify_css(&css).map_err(|e| Error::build(&source, format!("压缩失败: {}", e)))?;
+        }
+        let file_name = hashed_file_name(&source, css.as_bytes());
+        if config.build.minify {
+            original_sizes.insert(file_name.clone(), original_size);
+        }
+        let path = assets_dir.join(&file_name);
+        std::fs::write(&path, &css).map_err(|e| Error::io(&path, e))?;
+        emitted.push((file_name.clone(), css.len()));
+        html = replace_attr(&html, "href", &href, &config.asset_url(&file_name));
+    }
+    let html_out = out_dir.join("index.html");
+    std::fs::write(&html_out, &html).map_err(|e| Error::io(&html_out, e))?;
+
+    report(config, &emitted, &original_sizes, html.len());
+    println!("[build] 完成，用时 {:?}", started.elapsed());
+    Ok(())
+}
+
+/// html 里的 <script type="module" src>：(src 原来的值, 入口名, 文件路径)。
+/// 入口名用文件名，重名的时候加上序号
+fn html_entries(config: &Config, html: &str) -> Result<Vec<(String, String, PathBuf)>, Error> {
+    let resolver = Resolver::from_config(config);
+    let mut names = HashSet::new();
+    let mut entries = Vec::new();
+    for script in scan_html_scripts(html) {
+        let src = match script {
+            HtmlScript::Src(src) => src,
+            HtmlScript::Inline(code) => {
+                if !analyze_imports(&code).imports.is_empty() {
+                    println!(
+                        "[build] index.html 里内联的 <script type=\"module\"> 不会被打包，原样保留"
+                    );
+                }
+                continue;
+            }
+        };
+        if src.starts_with("http://") || src.starts_with("https://") || src.starts_with("//") {
+            continue;
+        }
+        // src="src/main.ts" 也是相对 index.html 的
+        let specifier = if src.starts_with('/') || src.starts_with('.') {
+            src.clone()
+        } else {
+            format!("./{}", src)
+        };
+        let path = resolver
+            .resolve(&specifier, &config.root, ResolveKind::Import)
+            .ok_or_else(|| Error::Resolve {
+                specifier: src.to_string(),
+                importer: config.root.join("index.html").display().to_string(),
+            })?;
+        let stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("index")
+            .to_string();
+        let mut name = stem.clone();
+        let mut i = 1;
+        while !names.insert(name.clone()) {
+            name = format!("{}{}", stem, i);
+            i += 1;
+        }
+        entries.push((src, name, path));
+    }
+    Ok(entries)
+}
+
+/// 给打包出来的文件加上内容 hash。
+/// 文件之间用 "./xxx.js" 互相导入，被导入的文件要先确定名字，导入它的文件内容才能确定。
+/// import() 可能让几个文件互相引用，这时候就用改名之前的内容算 hash
+fn hash_outputs(outputs: Vec<BundleOutput>) -> (Vec<BundleOutput>, HashMap<String, String>) {
+    let mut renamed: HashMap<String, String> = HashMap::new();
+    let mut files = Vec::new();
+    let mut pending = outputs;
+    while !pending.is_empty() {
+        let names: Vec<String> = pending.iter().map(|o| o.file_name.clone()).collect();
+        let (mut ready, rest): (Vec<BundleOutput>, Vec<BundleOutput>) =
+            pending.into_iter().partition(|output| {
+                !names
+                    .iter()
+                    .any(|name| *name != output.file_name && output.code.contains(&reference(name)))
+            });
+        if ready.is_empty() {
+            ready = rest;
+            pending = Vec::new();
+        } else {
+            pending = rest;
+        }
+
+        let mut batch = Vec::new();
+        for output in ready {
+            let code = replace_references(output.code, &renamed);
+            // chunk-1a2b3c4d.js 里的 hash 只和入口有关，换成内容的 hash
+            let path = Path::new(&output.file_name);
+            let file_name = if output.file_name.starts_with("chunk-") {
+                let ext = path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .unwrap_or("js");
+                hashed_file_name(Path::new(&format!("chunk.{}", ext)), code.as_bytes())
+            } else {
+                hashed_file_name(path, code.as_bytes())
+            };
+            batch.push((output.file_name, file_name, code));
+        }
+        for (from, to, _) in &batch {
+            renamed.insert(from.clone(), to.clone());
+        }
+        for (_, file_name, code) in batch {
+            let code = replace_references(code, &renamed);
+            files.push(BundleOutput { file_name, code });
+        }
+    }
+    (files, renamed)
+}
+
+fn reference(file_name: &str) -> String {
+    format!("\"./{}\"", file_name)
+}
+
+fn replace_references(mut code: String, renamed: &HashMap<String, String>) -> String {
+    for (from, to) in renamed {
+        code = code.replace(&reference(from), &reference(to));
+    }
+    code
+}
+
+/// 清空 outDir 再把 publicDir 复制进去。outDir 不在项目里的时候不敢删，只是覆盖
+fn prepare_out_dir(config: &Config, out_dir: &Path) -> Result<(), Error> {
+    let inside_root = out_dir.starts_with(&config.root)
+        && out_dir != config.root
+        && !out_dir.components().any(|c| c == Component::ParentDir);
+    if inside_root {
+        if out_dir.exists() {
+            std::fs::remove_dir_all(out_dir).map_err(|e| Error::io(out_dir, e))?;
+        }
+    } else {
+        println!(
+            "[build] {} 不在项目目录里，不会清空，只覆盖同名文件",
+            out_dir.display()
+        );
+    }
+    std::fs::create_dir_all(out_dir).map_err(|e| Error::io(out_dir, e))?;
+    let public_dir = config.public_dir();
+    if public_dir.is_dir() {
+        copy_dir(&public_dir, out_dir).map_err(|e| Error::io(&public_dir, e))?;
+    }
+    Ok(())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(from)?.flatten() {
+        let target = to.join(entry.file_name());
+        if entry.path().is_dir() {
+            std::fs::create_dir_all(&target)?;
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// html 里引用的项目里的文件，外部链接和 publicDir 里的文件不用处理
+fn local_file(config: &Config, url: &str) -> Option<PathBuf> {
+    if url.starts_with("http://") || url.starts_with("https://") || url.starts_with("//") {
+        return None;
+    }
+    let relative = url.trim_start_matches('/');
+    if config.public_dir().join(relative).is_file() {
+        return None;
+    }
+    let file = config.root.join(relative);
+    file.is_file().then_some(file)
+}
+
+/// 在 </head> 前面加上 <link rel="stylesheet">，缩进和 </head> 里面的元素对齐
+fn inject_stylesheets(html: &str, hrefs: &[String]) -> String {
+    let Some(index) = html.find("</head>") else {
+        let links: String = hrefs
+            .iter()
+            .map(|href| format!("<link rel=\"stylesheet\" href=\"{}\">\n", href))
+            .collect();
+        return format!("{}{}", links, html);
+    };
+    let line_start = html[..index].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let indent = &html[line_start..index];
+    let indent = if indent.trim().is_empty() { indent } else { "" };
+    let links: String = hrefs
+        .iter()
+        .map(|href| format!("  <link rel=\"stylesheet\" href=\"{}\">\n{}", href, indent))
+        .collect();
+    format!("{}{}{}", &html[..index], links, &html[index..])
+}
+
+/// 把 html 里 name="old" 的属性值换成 new
+fn replace_attr(html: &str, name: &str, old: &str, new: &str) -> String {
+    let mut html = html.to_string();
+    for quote in ['"', '\''] {
+        html = html.replace(
+            &format!("{}={}{}{}", name, quote, old, quote),
+            &format!("{}={}{}{}", name, quote, new, quote),
+        );
+    }
+    html
+}
+
+/// 输出每个文件的大小，压缩过的 js 带上压缩前的大小
+fn report(
+    config: &Config,
+    emitted: &[(String, usize)],
+    original_sizes: &HashMap<String, usize>,
+    html_size: usize,
+) {
+    let prefix = Path::new(&config.build.out_dir);
+    let mut rows: Vec<(String, usize, Option<usize>)> = emitted
+        .iter()
+        .map(|(file_name, size)| {
+            let path = prefix.join(&config.build.assets_dir).join(file_name);
+            (
+                path.to_string_lossy().replace('\\', "/"),
+                *size,
+                original_sizes.get(file_name.as_str()).copied(),
+            )
+        })
+        .collect();
+    rows.push((
+        prefix
+            .join("index.html")
+            .to_string_lossy()
+            .replace('\\', "/"),
+        html_size,
+        None,
+    ));
+    let width = rows
+        .iter()
+        .map(|(path, _, _)| path.len())
+        .max()
+        .unwrap_or(0);
+    for (path, size, original) in rows {
+        let original = original
+            .map(|original| format!("  (压缩前 {})", format_size(original)))
+            .unwrap_or_default();
+        println!(
+            "  {:<width$}  {:>10}{}",
+            path,
+            format_size(size),
+            original,
+            width = width
+        );
+    }
+}
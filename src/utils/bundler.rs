@@ -0,0 +1,1488 @@
+use crate::plugins::asset::{data_url, AssetPlugin, AssetQuery};
+use crate::plugins::css::CssPlugin;
+use crate::plugins::glob::GlobPlugin;
+use crate::plugins::json::JsonPlugin;
+use crate::plugins::typescript::TypescriptPlugin;
+use crate::plugins::{self, Command, ModuleSource, PluginContainer};
+use crate::utils::config::Config;
+use crate::utils::css::{asset_placeholder, css_to_js, CssUrls};
+use crate::utils::error::Error;
+use crate::utils::fs;
+use crate::utils::resolver::{ResolveKind, Resolver, EMPTY_MODULE};
+use crate::utils::transform::transform_typescript;
+use oxc_allocator::Allocator;
+use oxc_ast::ast::{
+    Argument, AssignmentExpression, AssignmentTarget, BinaryOperator, CallExpression,
+    ConditionalExpression, Declaration, ExportDefaultDeclarationKind, Expression, IfStatement,
+    ImportDeclarationSpecifier, ImportExpression, ObjectPropertyKind, Program, Statement,
+    StaticMemberExpression, StringLiteral,
+};
+use oxc_ast::visit::walk;
+use oxc_ast::{AstKind, Visit};
+use oxc_parser::{Parser, ParserReturn};
+use oxc_semantic::{Semantic, SemanticBuilder, SymbolTable};
+use oxc_span::{GetSpan, SourceType, Span};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// 打包器的版本，产物的格式变了要改一下，让旧的预构建缓存失效
+pub const BUNDLER_VERSION: &str = "arashi-bundler-3";
+
+/// import() 的路径先写成 "arashi-chunk:模块 id"，生成文件的时候换成 chunk 的相对路径
+const DYNAMIC_CHUNK: &str = "arashi-chunk:";
+
+/// import() 之前要预加载的文件先写成 "arashi-preload:模块 id"，生成文件的时候换成文件列表
+const DYNAMIC_PRELOAD: &str = "arashi-preload:";
+
+/// 当成 js 处理的文件，其他的（除了 css 和 json）都当成图片、字体这类资源
+const SCRIPT_EXTENSIONS: [&str; 9] = ["js", "mjs", "cjs", "jsx", "ts", "tsx", "mts", "cts", ""];
+
+/// 所有产物共用的模块注册表，预构建的依赖和 chunk 都从这里导入
+pub const RUNTIME_FILE: &str = "arashi-runtime.js";
+
+const RUNTIME: &str = r#"const factories = {};
+const cache = {};
+
+export function __define(id, factory) {
+  // 单独构建的依赖可能又带了一份已经注册过的模块，用先注册的那份，保证只有一个实例
+  if (!(id in factories)) factories[id] = factory;
+}
+
+export function __require(id) {
+  const cached = cache[id];
+  if (cached) return cached.exports;
+  const factory = factories[id];
+  if (!factory) throw new Error(`[arashi] module not found: ${id}`);
+  const module = (cache[id] = { exports: {} });
+  factory.call(module.exports, module, module.exports, __require);
+  return module.exports;
+}
+
+const namespaces = new WeakMap();
+
+// esm 导入 CommonJS 模块：module.exports 作为 default，它的属性作为具名导出。
+// babel / tsc 编译出来的模块（__esModule）本来就是 esm 的形状，不用再包
+export function __toESM(exports) {
+  if (exports && exports.__esModule) return exports;
+  if (exports === null || (typeof exports !== "object" && typeof exports !== "function")) {
+    return { default: exports };
+  }
+  let ns = namespaces.get(exports);
+  if (!ns) {
+    // 后面才挂上去的属性也能通过原型链拿到
+    ns = Object.create(exports);
+    for (const name in exports) {
+      if (name !== "default") {
+        Object.defineProperty(ns, name, { get: () => exports[name], enumerable: true });
+      }
+    }
+    Object.defineProperty(ns, "default", { value: exports, enumerable: true });
+    namespaces.set(exports, ns);
+  }
+  return ns;
+}
+
+export function __export(target, getters) {
+  Object.defineProperty(target, "__esModule", { value: true });
+  for (const name in getters) {
+    Object.defineProperty(target, name, { get: getters[name], enumerable: true });
+  }
+}
+
+export function __reexport(target, source) {
+  for (const name in source) {
+    if (name !== "default" && !Object.prototype.hasOwnProperty.call(target, name)) {
+      Object.defineProperty(target, name, { get: () => source[name], enumerable: true });
+    }
+  }
+}
+
+const preloaded = {};
+
+// import() 的 chunk 加载之前，先把它依赖的 chunk 用 modulepreload 并行加载，
+// 它的 css 加载完了再执行，页面上不会闪一下没有样式的内容。
+// deps 是相对输出目录的路径，和这个文件在同一个目录
+export function __preload(load, deps) {
+  if (!deps.length || typeof document === "undefined") return load();
+  return Promise.all(
+    deps.map((dep) => {
+      const href = new URL(dep, import.meta.url).href;
+      if (href in preloaded) return preloaded[href];
+      const isCss = href.endsWith(".css");
+      const rel = isCss ? "stylesheet" : "modulepreload";
+      // html 里已经引用了的 css 不用再加载
+      for (const link of document.querySelectorAll(`link[rel="${rel}"]`)) {
+        if (link.href === href) return (preloaded[href] = undefined);
+      }
+      const link = document.createElement("link");
+      link.rel = rel;
+      link.href = href;
+      document.head.appendChild(link);
+      // css 加载失败也不影响 chunk 执行
+      preloaded[href] = isCss
+        ? new Promise((resolve) => {
+            link.addEventListener("load", resolve);
+            link.addEventListener("error", resolve);
+          })
+        : undefined;
+      return preloaded[href];
+    })
+  ).then(load);
+}
+"#;
+
+/// 打包出来的一个文件，file_name 是相对输出目录的路径
+#[derive(Debug)]
+pub struct BundleOutput {
+    pub file_name: String,
+    pub code: String,
+}
+
+/// 打包时的一个模块，代码已经改写成注册表的形式，但还没有包上 __define
+struct Module {
+    id: String,
+    code: String,
+    /// CommonJS 模块，module.exports 整个就是 default 导出
+    cjs: bool,
+    /// 静态导出的名字（不包括 export * 带进来的）
+    exports: Vec<String>,
+    /// export * from 的模块，CommonJS 里是 module.exports = require('./x')
+    star_exports: Vec<PathBuf>,
+    deps: Vec<PathBuf>,
+    /// import() 的模块，它们是单独的入口，放在按需加载的 chunk 里
+    dynamic_deps: Vec<PathBuf>,
+    /// 生产构建时从 css 模块里抽出来的样式，url() 引用的文件还是占位符
+    css: Option<String>,
+}
+
+/// 一个产物文件：入口（html 或者依赖的入口、import() 的模块）或者共享的 chunk
+struct Chunk<'m> {
+    file_name: String,
+    /// 静态入口加载完要执行入口模块，import() 的入口由调用方自己 __require
+    entry: Option<(&'m PathBuf, bool)>,
+    modules: Vec<&'m Module>,
+    /// 加载之前要先加载的文件：入口静态依赖的模块所在的其他 chunk
+    imports: BTreeSet<String>,
+}
+
+/// 把第三方依赖打包成浏览器能直接加载的 esm 文件
+///
+/// 每个依赖的模块都注册到共享的模块注册表里，入口文件再把依赖的导出转成静态的 esm 导出。
+/// 被多个入口用到的模块放到共享的 chunk 里，比如 react 和 react-dom/client 用的是同一份 react
+pub fn bundle(config: &Config, entries: &[(String, PathBuf)]) -> Result<Vec<BundleOutput>, Error> {
+    Bundler::new(config, false).bundle(entries).map_err(|e| {
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        Error::dep_build(&names.join(", "), e)
+    })
+}
+
+/// 生产构建：源码和依赖打包在一起，图片这类资源输出成带 hash 的文件
+/// 源码和开发服务器一样经过插件的 load / transform
+pub fn bundle_app(config: &Config, entries: &[(String, PathBuf)]) -> Result<AppBundle, Error> {
+    let project_plugins =
+        async_std::task::block_on(plugins::project_plugins(config, Command::Build))?;
+    let plugins = PluginContainer::new(
+        Arc::new(config.clone()),
+        Command::Build,
+        vec![
+            Box::new(AssetPlugin),
+            Box::new(TypescriptPlugin::new()),
+            Box::new(GlobPlugin),
+            Box::new(CssPlugin),
+            Box::new(JsonPlugin),
+        ],
+        project_plugins,
+    );
+    let bundler = Bundler {
+        plugins: Some(&plugins),
+        ..Bundler::new(config, true)
+    };
+    let outputs = bundler.bundle(entries)?;
+    let assets = bundler
+        .assets
+        .map(|assets| assets.into_inner().into_iter().collect())
+        .unwrap_or_default();
+    Ok(AppBundle {
+        outputs,
+        assets,
+        css: bundler.entry_css.into_inner(),
+    })
+}
+
+/// bundle_app 的结果，assets 是 (源文件, 输出的文件名)，
+/// css 是每个入口要在 html 里引用的 .css 文件，按导入的顺序排好
+pub struct AppBundle {
+    pub outputs: Vec<BundleOutput>,
+    pub assets: Vec<(PathBuf, String)>,
+    pub css: BTreeMap<String, Vec<String>>,
+}
+
+struct Bundler<'c> {
+    config: &'c Config,
+    resolver: Resolver,
+    /// process.env.NODE_ENV 替换成的值
+    node_env: String,
+    /// 生产构建时导入的图片、字体等资源：源文件 => 带 hash 的文件名
+    assets: Option<RefCell<BTreeMap<PathBuf, String>>>,
+    /// 资源模块 => 导入它得到的 url，小文件是 data url，css 里的 url() 也换成它
+    asset_urls: RefCell<HashMap<PathBuf, String>>,
+    /// chunk 名字 => 放进去的包，只有生产构建才有
+    manual_chunks: BTreeMap<String, Vec<String>>,
+    /// 生产构建时转换源码的插件，预构建依赖不经过插件
+    plugins: Option<&'c PluginContainer>,
+    /// 入口名 => 它用到的 .css 文件
+    entry_css: RefCell<BTreeMap<String, Vec<String>>>,
+}
+
+impl<'c> Bundler<'c> {
+    /// app 为 true 是生产构建，false 是预构建依赖
+    fn new(config: &'c Config, app: bool) -> Self {
+        Bundler {
+            config,
+            resolver: Resolver::from_config(config),
+            node_env: config.node_env(),
+            assets: app.then(|| RefCell::new(BTreeMap::new())),
+            asset_urls: RefCell::new(HashMap::new()),
+            manual_chunks: if app {
+                config.build.manual_chunks.clone()
+            } else {
+                BTreeMap::new()
+            },
+            plugins: None,
+            entry_css: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    fn bundle(&self, entries: &[(String, PathBuf)]) -> Result<Vec<BundleOutput>, Error> {
+        let mut modules: HashMap<PathBuf, Module> = HashMap::new();
+        let mut entry_paths = Vec::new();
+        for (name, path) in entries {
+            let path = path
+                .canonicalize()
+                .map_err(|_| Error::build(path, format!("entry of \"{}\" does not exist", name)))?;
+            let mut queue = VecDeque::from([path.clone()]);
+            while let Some(file) = queue.pop_front() {
+                if modules.contains_key(&file) {
+                    continue;
+                }
+                let module = self.load(&file)?;
+                queue.extend(module.deps.iter().cloned());
+                queue.extend(module.dynamic_deps.iter().cloned());
+                modules.insert(file, module);
+            }
+            entry_paths.push((name.clone(), path));
+        }
+
+        let chunks = self.split_chunks(&modules, &entry_paths)?;
+        // 入口模块（包括 import() 的）=> 它所在的文件
+        let entry_files: HashMap<&PathBuf, &str> = chunks
+            .iter()
+            .filter_map(|chunk| {
+                chunk
+                    .entry
+                    .map(|(path, _)| (path, chunk.file_name.as_str()))
+            })
+            .collect();
+
+        let mut outputs = vec![BundleOutput {
+            file_name: RUNTIME_FILE.to_string(),
+            code: RUNTIME.to_string(),
+        }];
+
+        // 每个 chunk 里的模块抽出来的 css 按执行的顺序拼成一个 .css 文件
+        let static_entries: Vec<&PathBuf> = entry_paths.iter().map(|(_, path)| path).collect();
+        let dynamic_entries = chunks.iter().filter_map(|chunk| match chunk.entry {
+            Some((path, false)) => Some(path),
+            _ => None,
+        });
+        let all_entries: Vec<&PathBuf> =
+            static_entries.into_iter().chain(dynamic_entries).collect();
+        let position: HashMap<&str, usize> = execution_order(&modules, &all_entries)
+            .into_iter()
+            .enumerate()
+            .map(|(i, path)| (modules[path].id.as_str(), i))
+            .collect();
+        // 模块 id => 它所在的 chunk 的 .css 文件
+        let mut css_file_of: HashMap<&str, String> = HashMap::new();
+        for chunk in &chunks {
+            let mut styled: Vec<&Module> = chunk
+                .modules
+                .iter()
+                .filter(|module| module.css.is_some())
+                .copied()
+                .collect();
+            if styled.is_empty() {
+                continue;
+            }
+            styled.sort_by_key(|module| position.get(module.id.as_str()));
+            let file_name = css_file_name(&chunk.file_name);
+            let parts: Vec<String> = styled
+                .iter()
+                .map(|module| self.resolve_css_assets(&modules, module))
+                .collect();
+            for module in styled {
+                css_file_of.insert(&module.id, file_name.clone());
+            }
+            outputs.push(BundleOutput {
+                file_name,
+                code: concat_css(&parts),
+            });
+        }
+        // 入口点（包括 import() 的）按导入的顺序用到的 .css 文件：
+        // html 的入口在 html 里引用，import() 的在加载 chunk 之前预加载
+        let mut preloads: HashMap<&PathBuf, Vec<String>> = HashMap::new();
+        for chunk in &chunks {
+            let Some((path, is_static)) = chunk.entry else {
+                continue;
+            };
+            let mut css_files: Vec<String> = Vec::new();
+            for module in execution_order(&modules, &[path]) {
+                if let Some(file_name) = css_file_of.get(modules[module].id.as_str()) {
+                    if !css_files.contains(file_name) {
+                        css_files.push(file_name.clone());
+                    }
+                }
+            }
+            if is_static {
+                let name = chunk.file_name.trim_end_matches(".js").to_string();
+                self.entry_css.borrow_mut().insert(name, css_files);
+            } else {
+                let files = chunk.imports.iter().cloned().chain(css_files).collect();
+                preloads.insert(path, files);
+            }
+        }
+
+        for chunk in &chunks {
+            let prefix = relative_prefix(&chunk.file_name);
+            let mut code = runtime_import(&prefix);
+            for import in &chunk.imports {
+                code.push_str(&format!("import \"{}{}\";\n", prefix, import));
+            }
+            for module in &chunk.modules {
+                let mut defined = define(module);
+                for dep in &module.dynamic_deps {
+                    defined = defined.replace(
+                        &format!("\"{}{}\"", DYNAMIC_CHUNK, modules[dep].id),
+                        &format!("\"{}{}\"", prefix, entry_files[dep]),
+                    );
+                    // 预加载的文件是相对 runtime 的，runtime 在输出目录的最外层
+                    let files: Vec<String> = preloads
+                        .get(dep)
+                        .into_iter()
+                        .flatten()
+                        .map(|file| format!("./{}", file))
+                        .collect();
+                    defined = defined.replace(
+                        &format!("\"{}{}\"", DYNAMIC_PRELOAD, modules[dep].id),
+                        &serde_json::to_string(&files).unwrap_or_default(),
+                    );
+                }
+                code.push_str(&defined);
+            }
+            if let Some((path, true)) = chunk.entry {
+                let mut seen = BTreeSet::new();
+                let names = collect_exports(&modules, path, &mut seen);
+                code.push_str(&entry_exports(&modules[path].id, &names));
+            }
+            outputs.push(BundleOutput {
+                file_name: chunk.file_name.clone(),
+                code,
+            });
+        }
+        Ok(outputs)
+    }
+
+    /// 把模块分到各个文件里：
+    /// 1. 每个入口和每个 import() 的模块都是一个入口点，各自有一个文件
+    /// 2. manualChunks 里的包（连同它们的依赖）放到指定名字的 chunk 里
+    /// 3. 其他模块看能被哪些入口点静态地走到：只有一个就放在那个入口点的文件里，
+    ///    有多个就按这组入口点放到共享的 chunk 里，不会重复打包
+    ///
+    /// 文件名只和入口、模块路径有关，同样的代码每次构建的结果都一样
+    fn split_chunks<'m>(
+        &self,
+        modules: &'m HashMap<PathBuf, Module>,
+        entry_paths: &'m [(String, PathBuf)],
+    ) -> Result<Vec<Chunk<'m>>, Error> {
+        let mut entry_points: Vec<(String, &PathBuf, bool)> = entry_paths
+            .iter()
+            .map(|(name, path)| (name.clone(), path, true))
+            .collect();
+        let mut names: HashSet<String> = entry_paths.iter().map(|(name, _)| name.clone()).collect();
+        let static_entries: HashSet<&PathBuf> = entry_paths.iter().map(|(_, path)| path).collect();
+        let dynamic: BTreeMap<&str, &PathBuf> = modules
+            .values()
+            .flat_map(|module| module.dynamic_deps.iter())
+            .filter(|path| !static_entries.contains(path))
+            .map(|path| (modules[path].id.as_str(), path))
+            .collect();
+        for path in dynamic.into_values() {
+            let stem = dynamic_chunk_name(path);
+            let mut name = stem.clone();
+            let mut i = 1;
+            while !names.insert(name.clone()) {
+                name = format!("{}{}", stem, i);
+                i += 1;
+            }
+            entry_points.push((name, path, false));
+        }
+
+        let manual = self.manual_chunk_of(modules);
+        for chunk in manual.values() {
+            if entry_paths.iter().any(|(name, _)| name == chunk) {
+                return Err(Error::build(
+                    self.config
+                        .config_file
+                        .as_deref()
+                        .unwrap_or(&self.config.root),
+                    format!("manualChunks 里的 \"{}\" 和入口重名", chunk),
+                ));
+            }
+        }
+
+        // 每个入口点静态地能走到哪些模块
+        let mut owners: BTreeMap<&PathBuf, BTreeSet<&str>> = BTreeMap::new();
+        let mut reachable: Vec<Vec<&PathBuf>> = Vec::new();
+        for (name, path, _) in &entry_points {
+            let mut seen = Vec::new();
+            let mut queue = VecDeque::from([*path]);
+            while let Some(file) = queue.pop_front() {
+                if !owners.entry(file).or_default().insert(name.as_str()) {
+                    continue;
+                }
+                seen.push(file);
+                queue.extend(modules[file].deps.iter());
+            }
+            reachable.push(seen);
+        }
+
+        let mut file_of: HashMap<&PathBuf, String> = HashMap::new();
+        let mut chunks: BTreeMap<String, Chunk> = BTreeMap::new();
+        for (name, path, is_static) in &entry_points {
+            let file_name = format!("{}.js", name);
+            chunks.insert(
+                file_name.clone(),
+                Chunk {
+                    file_name,
+                    entry: Some((*path, *is_static)),
+                    modules: Vec::new(),
+                    imports: BTreeSet::new(),
+                },
+            );
+        }
+        for (file, entry_owners) in &owners {
+            let file_name = match manual.get(file) {
+                Some(chunk) => format!("{}.js", chunk),
+                None if entry_owners.len() == 1 => {
+                    format!("{}.js", entry_owners.iter().next().unwrap())
+                }
+                None => chunk_name(entry_owners),
+            };
+            chunks
+                .entry(file_name.clone())
+                .or_insert_with(|| Chunk {
+                    file_name: file_name.clone(),
+                    entry: None,
+                    modules: Vec::new(),
+                    imports: BTreeSet::new(),
+                })
+                .modules
+                .push(&modules[*file]);
+            file_of.insert(file, file_name);
+        }
+
+        // 入口点要先把它静态依赖的模块所在的文件都加载了
+        for ((name, _, _), seen) in entry_points.iter().zip(&reachable) {
+            let own = format!("{}.js", name);
+            let imports: BTreeSet<String> = seen
+                .iter()
+                .map(|file| file_of[file].clone())
+                .filter(|file_name| *file_name != own)
+                .collect();
+            if let Some(chunk) = chunks.get_mut(&own) {
+                chunk.imports = imports;
+            }
+        }
+        Ok(chunks.into_values().collect())
+    }
+
+    /// manualChunks 里的包的模块 => chunk 名字。
+    /// 包的依赖也跟着放进去（除非它已经在别的 manualChunks 里）
+    fn manual_chunk_of<'m>(
+        &self,
+        modules: &'m HashMap<PathBuf, Module>,
+    ) -> HashMap<&'m PathBuf, String> {
+        let mut manual: HashMap<&PathBuf, String> = HashMap::new();
+        let mut queue = VecDeque::new();
+        for (chunk, packages) in &self.manual_chunks {
+            let mut matched: Vec<&PathBuf> = modules
+                .keys()
+                .filter(|path| {
+                    let path = path.to_string_lossy().replace('\\', "/");
+                    packages
+                        .iter()
+                        .any(|pkg| path.contains(&format!("/node_modules/{}/", pkg)))
+                })
+                .collect();
+            matched.sort();
+            for path in matched {
+                if !manual.contains_key(path) {
+                    manual.insert(path, chunk.clone());
+                    queue.push_back((path, chunk));
+                }
+            }
+        }
+        while let Some((path, chunk)) = queue.pop_front() {
+            for dep in &modules[path].deps {
+                if !manual.contains_key(dep) {
+                    manual.insert(dep, chunk.clone());
+                    queue.push_back((dep, chunk));
+                }
+            }
+        }
+        manual
+    }
+
+    fn load(&self, path: &Path) -> Result<Module, Error> {
+        if path == Path::new(EMPTY_MODULE) {
+            return Ok(Module {
+                id: EMPTY_MODULE.to_string(),
+                code: String::new(),
+                cjs: true,
+                exports: Vec::new(),
+                star_exports: Vec::new(),
+                deps: Vec::new(),
+                dynamic_deps: Vec::new(),
+                css: None,
+            });
+        }
+        let id = self.module_id(path);
+        // ?raw、?url 这类查询参数带在模块的路径上，读的是查询参数前面的文件
+        let file = strip_query(path);
+        let ext = file.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        // 插件转换过的模块都是 esm，没有插件处理的文件走下面内置的逻辑
+        let mut css = None;
+        let (source, source_type) = match self.load_with_plugins(path, &id)? {
+            Some(loaded) => {
+                css = loaded.css;
+                (loaded.code, SourceType::mjs())
+            }
+            None => {
+                let force_url = matches!(
+                    path.to_str().and_then(AssetQuery::parse),
+                    Some(AssetQuery::Url | AssetQuery::Import)
+                );
+                if force_url || !SCRIPT_EXTENSIONS.contains(&ext) && ext != "css" && ext != "json" {
+                    return self.load_asset(&file, id, !force_url);
+                }
+                let source = fs::read_file_content(&file).map_err(|e| Error::io(&file, e))?;
+                // 生产构建时 json 由 arashi:json 转成 esm，预构建依赖里 require 的 json 还是 CommonJS
+                if ext == "json" {
+                    return load_json(path, id, &source);
+                }
+
+                // 和开发服务器一样的转换：ts / jsx 转成 js，css 转成插入 style 标签的 js
+                let source = match ext {
+                    "ts" | "tsx" | "jsx" | "mts" | "cts" => {
+                        transform_typescript(&source, ext != "ts", false, self.config, None)
+                            .map_err(|error| Error::Transform {
+                                plugin: "arashi:typescript",
+                                file: path.to_path_buf(),
+                                error,
+                            })?
+                            .code
+                    }
+                    // 预构建依赖只在开发服务器里用，url() 指向开发服务器上的文件
+                    "css" => {
+                        css_to_js(&source, &file, self.config, CssUrls::Serve, false)
+                            .map_err(|e| Error::Transform {
+                                plugin: "arashi:css",
+                                file: e.file,
+                                error: e.error,
+                            })?
+                            .code
+                    }
+                    _ => source,
+                };
+                // .js 文件有 import / export 就是 esm，否则当成 CommonJS
+                let source_type = match ext {
+                    "mjs" | "ts" | "tsx" | "jsx" | "mts" | "css" => SourceType::mjs(),
+                    "cjs" | "cts" => SourceType::cjs(),
+                    _ => SourceType::unambiguous(),
+                };
+                (source, source_type)
+            }
+        };
+        let allocator = Allocator::default();
+        let ParserReturn {
+            program,
+            errors,
+            panicked,
+            ..
+        } = Parser::new(&allocator, &source, source_type).parse();
+        if panicked || !errors.is_empty() {
+            let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            return Err(Error::build(path, messages.join("; ")));
+        }
+        let semantic = SemanticBuilder::new().build(&program).semantic;
+        let mut visitor = DepVisitor::new(semantic.symbols(), &self.node_env);
+        visitor.visit_program(&program);
+
+        let mut module = Module {
+            id,
+            code: String::new(),
+            cjs: !program.source_type.is_module(),
+            exports: Vec::new(),
+            star_exports: Vec::new(),
+            deps: Vec::new(),
+            dynamic_deps: Vec::new(),
+            css,
+        };
+        // (start, end, 替换成的内容)
+        let mut edits: Vec<(u32, u32, String)> = Vec::new();
+        for (span, specifier) in &visitor.dynamic_imports {
+            let dep = self.resolve(path, specifier, ResolveKind::Import)?;
+            // 先加载 import() 的模块所在的 chunk，路径和要预加载的文件等到分好 chunk 了再换
+            let id = self.module_id(&dep);
+            edits.push((
+                span.start,
+                span.end,
+                format!(
+                    "__preload(() => import(\"{}{}\"), \"{}{}\").then(() => __toESM(__require(\"{}\")))",
+                    DYNAMIC_CHUNK, id, DYNAMIC_PRELOAD, id, id
+                ),
+            ));
+            module.dynamic_deps.push(dep);
+        }
+        for span in &visitor.node_env {
+            edits.push((span.start, span.end, format!("\"{}\"", self.node_env)));
+        }
+
+        if module.cjs {
+            self.transform_cjs(path, &source, module, edits, visitor)
+        } else {
+            self.transform_esm(path, &source, module, edits, &program, &semantic)
+        }
+    }
+
+    /// 把 esm 改写成注册表里的模块：
+    /// import 变成 __require，用到导入变量的地方改成访问依赖的导出对象，export 变成 getter
+    fn transform_esm(
+        &self,
+        path: &Path,
+        source: &str,
+        mut module: Module,
+        mut edits: Vec<(u32, u32, String)>,
+        program: &Program,
+        semantic: &Semantic,
+    ) -> Result<Module, Error> {
+        let mut header = String::new();
+        let mut getters: Vec<(String, String)> = Vec::new();
+        // 导入变量 => 访问依赖导出的表达式
+        let mut bindings = HashMap::new();
+        let mut local_names = HashMap::new();
+        let mut removed: Vec<Span> = Vec::new();
+
+        let require = |specifier: &str, module: &mut Module, header: &mut String| {
+            let dep = self.resolve(path, specifier, ResolveKind::Import)?;
+            let var = format!("__arashi_m{}", module.deps.len());
+            // CommonJS 模块的 module.exports 要包一层，才有 default 和具名导出
+            header.push_str(&format!(
+                "var {} = __toESM(__require(\"{}\"));\n",
+                var,
+                self.module_id(&dep)
+            ));
+            module.deps.push(dep.clone());
+            Ok::<_, Error>((var, dep))
+        };
+
+        for stmt in &program.body {
+            match stmt {
+                Statement::ImportDeclaration(decl) => {
+                    let (var, _) = require(&decl.source.value, &mut module, &mut header)?;
+                    for specifier in decl.specifiers.iter().flatten() {
+                        let (local, expr) = match specifier {
+                            ImportDeclarationSpecifier::ImportSpecifier(s) => {
+                                (&s.local, member(&var, &s.imported.name()))
+                            }
+                            ImportDeclarationSpecifier::ImportDefaultSpecifier(s) => {
+                                (&s.local, member(&var, "default"))
+                            }
+                            ImportDeclarationSpecifier::ImportNamespaceSpecifier(s) => {
+                                (&s.local, var.clone())
+                            }
+                        };
+                        local_names.insert(local.name.to_string(), expr.clone());
+                        bindings.insert(local.symbol_id(), expr);
+                    }
+                    removed.push(decl.span);
+                }
+                Statement::ExportAllDeclaration(decl) => {
+                    let (var, dep) = require(&decl.source.value, &mut module, &mut header)?;
+                    match &decl.exported {
+                        Some(exported) => getters.push((exported.name().to_string(), var)),
+                        None => {
+                            header.push_str(&format!("__reexport(__arashi_exports, {});\n", var));
+                            module.star_exports.push(dep);
+                        }
+                    }
+                    removed.push(decl.span);
+                }
+                Statement::ExportNamedDeclaration(decl) => {
+                    if let Some(source) = &decl.source {
+                        let (var, _) = require(&source.value, &mut module, &mut header)?;
+                        for specifier in &decl.specifiers {
+                            getters.push((
+                                specifier.exported.name().to_string(),
+                                member(&var, &specifier.local.name()),
+                            ));
+                        }
+                        removed.push(decl.span);
+                    } else if let Some(declaration) = &decl.declaration {
+                        // 去掉 export 关键字，保留声明
+                        edits.push((decl.span.start, declaration.span().start, String::new()));
+                        for name in declared_names(declaration) {
+                            getters.push((name.clone(), name));
+                        }
+                    } else {
+                        for specifier in &decl.specifiers {
+                            let local = specifier.local.name().to_string();
+                            let expr = local_names.get(&local).cloned().unwrap_or(local);
+                            getters.push((specifier.exported.name().to_string(), expr));
+                        }
+                        removed.push(decl.span);
+                    }
+                }
+                Statement::ExportDefaultDeclaration(decl) => {
+                    let start = decl.declaration.span().start;
+                    let named = match &decl.declaration {
+                        ExportDefaultDeclarationKind::FunctionDeclaration(f) => f.name(),
+                        ExportDefaultDeclarationKind::ClassDeclaration(c) => {
+                            c.id.as_ref().map(|id| id.name)
+                        }
+                        _ => None,
+                    };
+                    match named {
+                        Some(name) => {
+                            edits.push((decl.span.start, start, String::new()));
+                            getters.push(("default".to_string(), name.to_string()));
+                        }
+                        None => {
+                            edits.push((
+                                decl.span.start,
+                                start,
+                                "var __arashi_default = ".to_string(),
+                            ));
+                            getters.push(("default".to_string(), "__arashi_default".to_string()));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // 用到导入变量的地方
+        let nodes = semantic.nodes();
+        for (symbol_id, expr) in &bindings {
+            for reference in semantic.symbols().get_resolved_references(*symbol_id) {
+                let AstKind::IdentifierReference(ident) = nodes.kind(reference.node_id()) else {
+                    continue;
+                };
+                let replacement = match nodes.parent_kind(reference.node_id()) {
+                    // { useState } => { useState: __arashi_m0.useState }
+                    Some(AstKind::ObjectProperty(prop)) if prop.shorthand => {
+                        format!("{}: {}", ident.name, expr)
+                    }
+                    // 直接调用的时候不能把依赖的导出对象当成 this
+                    Some(AstKind::CallExpression(call)) if call.callee.span() == ident.span => {
+                        format!("(0, {})", expr)
+                    }
+                    _ => expr.clone(),
+                };
+                edits.push((ident.span.start, ident.span.end, replacement));
+            }
+        }
+
+        // 被删掉的语句里面的改动就不用做了
+        edits.retain(|(start, end, _)| {
+            !removed
+                .iter()
+                .any(|span| span.start <= *start && *end <= span.end)
+        });
+        edits.extend(
+            removed
+                .iter()
+                .map(|span| (span.start, span.end, String::new())),
+        );
+        let code = apply_edits(source, edits);
+
+        module.exports = getters.iter().map(|(name, _)| name.clone()).collect();
+        let getters: Vec<String> = getters
+            .iter()
+            .map(|(name, expr)| format!("{}: () => {}", property_key(name), expr))
+            .collect();
+        module.code = format!(
+            "__export(__arashi_exports, {{ {} }});\n{}{}",
+            getters.join(", "),
+            header,
+            code
+        );
+        Ok(module)
+    }
+
+    /// CommonJS 模块保持原样，只把 require 的路径换成注册表里的 id，
+    /// 再像 cjs-module-lexer 那样静态找出 exports.xxx 这些具名导出
+    fn transform_cjs(
+        &self,
+        path: &Path,
+        source: &str,
+        mut module: Module,
+        mut edits: Vec<(u32, u32, String)>,
+        visitor: DepVisitor,
+    ) -> Result<Module, Error> {
+        let prefix = if self.assets.is_some() {
+            "build"
+        } else {
+            "deps"
+        };
+        for (span, specifier) in &visitor.requires {
+            match self.resolve(path, specifier, ResolveKind::Require) {
+                Ok(dep) => {
+                    edits.push((
+                        span.start,
+                        span.end,
+                        format!("\"{}\"", self.module_id(&dep)),
+                    ));
+                    module.deps.push(dep);
+                }
+                // 经常是包在 try/catch 里的可选依赖，故意不改，运行到的时候再报错
+                Err(_) => println!(
+                    "[{}] {} 里的 require(\"{}\") 找不到，保持原样",
+                    prefix,
+                    path.display(),
+                    specifier
+                ),
+            }
+        }
+        for specifier in &visitor.reexports {
+            if let Ok(dep) = self.resolve(path, specifier, ResolveKind::Require) {
+                module.star_exports.push(dep);
+            }
+        }
+        module.exports = visitor.exports;
+        module.code = apply_edits(source, edits);
+        Ok(module)
+    }
+
+    /// 图片、字体这类资源，模块的内容就是它的 url。
+    /// 生产构建时输出成带 hash 的文件，inline 的话小于 build.assetsInlineLimit 的文件内联成 data url；
+    /// 预构建依赖的时候直接用开发服务器上的路径
+    fn load_asset(&self, path: &Path, id: String, inline: bool) -> Result<Module, Error> {
+        let url = match &self.assets {
+            Some(assets) => {
+                let content = fs::read_file_bytes(path).map_err(|e| Error::io(path, e))?;
+                if inline && content.len() < self.config.build.assets_inline_limit {
+                    data_url(path, &content)
+                } else {
+                    let file_name = hashed_file_name(path, &content);
+                    let url = self.config.asset_url(&file_name);
+                    assets.borrow_mut().insert(path.to_path_buf(), file_name);
+                    url
+                }
+            }
+            None => format!("/{}", self.module_id(path)),
+        };
+        self.asset_urls
+            .borrow_mut()
+            .insert(path.to_path_buf(), url.clone());
+        Ok(Module {
+            code: format!(
+                "module.exports = {};\n",
+                serde_json::to_string(&url).unwrap_or_default()
+            ),
+            id,
+            cjs: true,
+            exports: Vec::new(),
+            star_exports: Vec::new(),
+            deps: Vec::new(),
+            dynamic_deps: Vec::new(),
+            css: None,
+        })
+    }
+
+    fn resolve(
+        &self,
+        importer: &Path,
+        specifier: &str,
+        kind: ResolveKind,
+    ) -> Result<PathBuf, Error> {
+        let importer_file = strip_query(importer);
+        let dir = importer_file.parent().unwrap_or(&self.config.root);
+        // 插件返回的 id 和开发服务器一样，以 / 开头的是相对项目根目录的路径
+        if let Some(plugins) = self.plugins {
+            let importer_id = format!("/{}", self.module_id(importer));
+            let resolved = async_std::task::block_on(plugins.resolve_id(specifier, &importer_id))?;
+            if let Some(id) = resolved {
+                return Ok(match id.strip_prefix('/') {
+                    Some(id) => self.config.root.join(id),
+                    None => dir.join(id),
+                });
+            }
+        }
+        // ?raw 这类查询参数解析完再加回去，同一个文件不同的查询参数是不同的模块
+        let (request, query) = match specifier.split_once('?') {
+            Some((request, query)) => (request, Some(query)),
+            None => (specifier, None),
+        };
+        let resolved = self
+            .resolver
+            .resolve(request, dir, kind)
+            .ok_or_else(|| Error::Resolve {
+                specifier: specifier.to_string(),
+                importer: importer.display().to_string(),
+            })?;
+        Ok(match query {
+            Some(query) if !query.is_empty() => {
+                let mut path = resolved.into_os_string();
+                path.push("?");
+                path.push(query);
+                PathBuf::from(path)
+            }
+            _ => resolved,
+        })
+    }
+
+    /// 插件 load 或者 transform 过的话返回转换后的结果，模块的 id 和开发服务器一样以 / 开头。
+    /// 打包是同步的，插件的钩子在这里阻塞等待
+    fn load_with_plugins(&self, path: &Path, id: &str) -> Result<Option<ModuleSource>, Error> {
+        let Some(plugins) = self.plugins else {
+            return Ok(None);
+        };
+        let id = format!("/{}", id.trim_start_matches('/'));
+        let loaded = async_std::task::block_on(plugins.load(&id))?;
+        let is_loaded = loaded.is_some();
+        let source = match loaded {
+            Some(source) => source,
+            // 图片这类二进制文件交给 load_asset
+            None => match fs::read_file_content(strip_query(path)) {
+                Ok(code) => ModuleSource::new(code),
+                Err(_) => return Ok(None),
+            },
+        };
+        let transformed = async_std::task::block_on(plugins.transform(source.clone(), &id))?;
+        Ok(match transformed {
+            Some(transformed) => Some(transformed),
+            None => is_loaded.then_some(source),
+        })
+    }
+
+    /// 模块 css 里 url() 的占位符换成输出的资源文件的 url，引用的文件都是模块的依赖
+    fn resolve_css_assets(&self, modules: &HashMap<PathBuf, Module>, module: &Module) -> String {
+        let mut css = module.css.clone().unwrap_or_default();
+        let asset_urls = self.asset_urls.borrow();
+        for dep in &module.deps {
+            if let Some(url) = asset_urls.get(dep) {
+                let placeholder = asset_placeholder(&format!("/{}", modules[dep].id));
+                css = css.replace(&placeholder, url);
+            }
+        }
+        css
+    }
+
+    /// 模块在注册表里的 id，用相对项目根目录的路径，不同的构建之间也保持一致
+    fn module_id(&self, path: &Path) -> String {
+        path.strip_prefix(&self.config.root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+}
+
+/// 找 import('...')、process.env.NODE_ENV，CommonJS 模块还要找 require('...') 和 exports.xxx
+///
+/// `if (process.env.NODE_ENV === "production")` 这种判断在打包时就能确定走哪边，
+/// 走不到的分支里的 require 不会被打包（react 的入口就是这样选 development 还是 production 版本的）
+struct DepVisitor<'s> {
+    symbols: &'s SymbolTable,
+    /// process.env.NODE_ENV 的值，用来判断分支走哪边
+    mode: &'s str,
+    dynamic_imports: Vec<(Span, String)>,
+    node_env: Vec<Span>,
+    /// require 的路径，span 包含引号
+    requires: Vec<(Span, String)>,
+    exports: Vec<String>,
+    reexports: Vec<String>,
+}
+
+impl<'s> DepVisitor<'s> {
+    fn new(symbols: &'s SymbolTable, mode: &'s str) -> Self {
+        DepVisitor {
+            symbols,
+            mode,
+            dynamic_imports: Vec::new(),
+            node_env: Vec::new(),
+            requires: Vec::new(),
+            exports: Vec::new(),
+            reexports: Vec::new(),
+        }
+    }
+
+    fn require_specifier<'e>(&self, expr: &'e Expression) -> Option<&'e StringLiteral<'e>> {
+        match expr.without_parentheses() {
+            Expression::CallExpression(call) => self.require_call(call),
+            _ => None,
+        }
+    }
+
+    /// require('x')，require 得是全局的，不能是模块自己定义的变量
+    fn require_call<'e>(&self, call: &'e CallExpression) -> Option<&'e StringLiteral<'e>> {
+        let Expression::Identifier(callee) = &call.callee else {
+            return None;
+        };
+        if callee.name != "require" || call.arguments.len() != 1 {
+            return None;
+        }
+        let is_global = self
+            .symbols
+            .get_reference(callee.reference_id())
+            .symbol_id()
+            .is_none();
+        match &call.arguments[0] {
+            Argument::StringLiteral(specifier) if is_global => Some(specifier),
+            _ => None,
+        }
+    }
+
+    fn add_export(&mut self, name: &str) {
+        if name != "__esModule" && !self.exports.iter().any(|n| n == name) {
+            self.exports.push(name.to_string());
+        }
+    }
+}
+
+impl<'a> Visit<'a> for DepVisitor<'_> {
+    fn visit_import_expression(&mut self, expr: &ImportExpression<'a>) {
+        if let Expression::StringLiteral(source) = &expr.source {
+            self.dynamic_imports
+                .push((expr.span, source.value.to_string()));
+        }
+        walk::walk_import_expression(self, expr);
+    }
+
+    fn visit_static_member_expression(&mut self, expr: &StaticMemberExpression<'a>) {
+        if is_node_env_member(expr) {
+            self.node_env.push(expr.span);
+            return;
+        }
+        walk::walk_static_member_expression(self, expr);
+    }
+
+    fn visit_if_statement(&mut self, stmt: &IfStatement<'a>) {
+        match node_env_condition(&stmt.test, self.mode) {
+            Some(taken) => {
+                self.visit_expression(&stmt.test);
+                if taken {
+                    self.visit_statement(&stmt.consequent);
+                } else if let Some(alternate) = &stmt.alternate {
+                    self.visit_statement(alternate);
+                }
+            }
+            None => walk::walk_if_statement(self, stmt),
+        }
+    }
+
+    fn visit_conditional_expression(&mut self, expr: &ConditionalExpression<'a>) {
+        match node_env_condition(&expr.test, self.mode) {
+            Some(taken) => {
+                self.visit_expression(&expr.test);
+                if taken {
+                    self.visit_expression(&expr.consequent);
+                } else {
+                    self.visit_expression(&expr.alternate);
+                }
+            }
+            None => walk::walk_conditional_expression(self, expr),
+        }
+    }
+
+    fn visit_call_expression(&mut self, call: &CallExpression<'a>) {
+        if let Some(specifier) = self.require_call(call) {
+            self.requires
+                .push((specifier.span, specifier.value.to_string()));
+        }
+        let expr = call.callee.without_parentheses();
+        if let Expression::Identifier(callee) = expr {
+            // tslib / babel 的 __exportStar(require('./x'), exports)
+            if callee.name == "__exportStar" || callee.name == "__export" {
+                let specifier = call
+                    .arguments
+                    .first()
+                    .and_then(Argument::as_expression)
+                    .and_then(|arg| self.require_specifier(arg));
+                if let Some(specifier) = specifier {
+                    self.reexports.push(specifier.value.to_string());
+                }
+            }
+        }
+        // Object.defineProperty(exports, 'name', ...)
+        if let Expression::StaticMemberExpression(callee) = expr {
+            let is_define_property = callee.property.name == "defineProperty"
+                && matches!(&callee.object, Expression::Identifier(object) if object.name == "Object");
+            let target = call.arguments.first().and_then(Argument::as_expression);
+            if is_define_property && target.map(is_exports_object).unwrap_or(false) {
+                if let Some(Argument::StringLiteral(name)) = call.arguments.get(1) {
+                    self.add_export(&name.value);
+                }
+            }
+        }
+        walk::walk_call_expression(self, call);
+    }
+
+    fn visit_assignment_expression(&mut self, expr: &AssignmentExpression<'a>) {
+        match &expr.left {
+            // module.exports = ...
+            AssignmentTarget::StaticMemberExpression(target)
+                if target.property.name == "exports"
+                    && matches!(&target.object, Expression::Identifier(object) if object.name == "module") =>
+            {
+                match expr.right.without_parentheses() {
+                    Expression::ObjectExpression(object) => {
+                        for property in &object.properties {
+                            match property {
+                                ObjectPropertyKind::ObjectProperty(property) => {
+                                    if let Some(name) = property.key.static_name() {
+                                        self.add_export(&name);
+                                    }
+                                }
+                                ObjectPropertyKind::SpreadProperty(spread) => {
+                                    if let Some(specifier) =
+                                        self.require_specifier(&spread.argument)
+                                    {
+                                        self.reexports.push(specifier.value.to_string());
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    right => {
+                        if let Some(specifier) = self.require_specifier(right) {
+                            self.reexports.push(specifier.value.to_string());
+                        }
+                    }
+                }
+            }
+            // exports.foo = ... / module.exports.foo = ...
+            AssignmentTarget::StaticMemberExpression(target)
+                if is_exports_object(&target.object) =>
+            {
+                self.add_export(&target.property.name);
+            }
+            // exports['foo'] = ...
+            AssignmentTarget::ComputedMemberExpression(target)
+                if is_exports_object(&target.object) =>
+            {
+                if let Expression::StringLiteral(name) = &target.expression {
+                    self.add_export(&name.value);
+                }
+            }
+            _ => {}
+        }
+        walk::walk_assignment_expression(self, expr);
+    }
+}
+
+fn is_node_env(expr: &Expression) -> bool {
+    match expr.without_parentheses() {
+        Expression::StaticMemberExpression(member) => is_node_env_member(member),
+        _ => false,
+    }
+}
+
+fn is_node_env_member(node_env: &StaticMemberExpression) -> bool {
+    let Expression::StaticMemberExpression(env) = &node_env.object else {
+        return false;
+    };
+    node_env.property.name == "NODE_ENV"
+        && env.property.name == "env"
+        && matches!(&env.object, Expression::Identifier(ident) if ident.name == "process")
+}
+
+/// process.env.NODE_ENV === "production" 这类判断的结果，判断不了返回 None
+fn node_env_condition(test: &Expression, mode: &str) -> Option<bool> {
+    let Expression::BinaryExpression(binary) = test.without_parentheses() else {
+        return None;
+    };
+    let value = match (&binary.left, &binary.right) {
+        (Expression::StringLiteral(value), other) | (other, Expression::StringLiteral(value))
+            if is_node_env(other) =>
+        {
+            value.value.as_str()
+        }
+        _ => return None,
+    };
+    let equal = value == mode;
+    match binary.operator {
+        BinaryOperator::Equality | BinaryOperator::StrictEquality => Some(equal),
+        BinaryOperator::Inequality | BinaryOperator::StrictInequality => Some(!equal),
+        _ => None,
+    }
+}
+
+/// exports 或者 module.exports
+fn is_exports_object(expr: &Expression) -> bool {
+    match expr.without_parentheses() {
+        Expression::Identifier(ident) => ident.name == "exports",
+        Expression::StaticMemberExpression(member) => {
+            member.property.name == "exports"
+                && matches!(&member.object, Expression::Identifier(object) if object.name == "module")
+        }
+        _ => false,
+    }
+}
+
+/// json 当成 CommonJS 模块，顶层的 key 作为具名导出
+fn load_json(path: &Path, id: String, source: &str) -> Result<Module, Error> {
+    let value: serde_json::Value =
+        serde_json::from_str(source).map_err(|e| Error::build(path, e))?;
+    let exports = value
+        .as_object()
+        .map(|object| object.keys().cloned().collect())
+        .unwrap_or_default();
+    Ok(Module {
+        id,
+        code: format!("module.exports = {};\n", source.trim()),
+        cjs: true,
+        exports,
+        star_exports: Vec::new(),
+        deps: Vec::new(),
+        dynamic_deps: Vec::new(),
+        css: None,
+    })
+}
+
+/// 从 entries 出发深度优先，依赖排在导入它的模块前面，也就是模块执行的顺序
+fn execution_order<'m>(
+    modules: &'m HashMap<PathBuf, Module>,
+    entries: &[&'m PathBuf],
+) -> Vec<&'m PathBuf> {
+    let mut order = Vec::new();
+    let mut seen = HashSet::new();
+    for entry in entries {
+        if !seen.insert(*entry) {
+            continue;
+        }
+        // (模块, 下一个要看的依赖)
+        let mut stack = vec![(*entry, 0)];
+        while let Some((path, index)) = stack.pop() {
+            match modules[path].deps.get(index) {
+                Some(dep) => {
+                    stack.push((path, index + 1));
+                    if seen.insert(dep) {
+                        stack.push((dep, 0));
+                    }
+                }
+                None => order.push(path),
+            }
+        }
+    }
+    order
+}
+
+/// 拼接几个模块的 css，外部的 @import 只能写在最前面
+fn concat_css(parts: &[String]) -> String {
+    let mut imports = String::new();
+    let mut rules = String::new();
+    for part in parts {
+        for line in part.lines() {
+            if line.starts_with("@import ") {
+                imports.push_str(line);
+                imports.push('\n');
+            } else {
+                rules.push_str(line);
+                rules.push('\n');
+            }
+        }
+    }
+    imports + &rules
+}
+
+/// main.js => main.css
+fn css_file_name(chunk: &str) -> String {
+    format!("{}.css", chunk.trim_end_matches(".js"))
+}
+
+/// 从后往前替换，前面的位置才不会错
+fn apply_edits(source: &str, mut edits: Vec<(u32, u32, String)>) -> String {
+    edits.sort_by_key(|(start, _, _)| *start);
+    let mut code = source.to_string();
+    for (start, end, replacement) in edits.into_iter().rev() {
+        code.replace_range(start as usize..end as usize, &replacement);
+    }
+    code
+}
+
+fn declared_names(declaration: &Declaration) -> Vec<String> {
+    match declaration {
+        Declaration::VariableDeclaration(decl) => decl
+            .declarations
+            .iter()
+            .flat_map(|d| d.id.get_binding_identifiers())
+            .map(|ident| ident.name.to_string())
+            .collect(),
+        Declaration::FunctionDeclaration(f) => {
+            f.name().map(|n| n.to_string()).into_iter().collect()
+        }
+        Declaration::ClassDeclaration(c) => c.id.iter().map(|id| id.name.to_string()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// 入口最终导出的名字，要把 export * 带进来的也算上（default 除外）。
+/// CommonJS 的入口不管有没有 exports.default，module.exports 都会作为 default 导出
+fn collect_exports(
+    modules: &HashMap<PathBuf, Module>,
+    path: &PathBuf,
+    seen: &mut BTreeSet<PathBuf>,
+) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    if !seen.insert(path.clone()) {
+        return names;
+    }
+    let module = &modules[path];
+    names.extend(module.exports.iter().cloned());
+    if module.cjs {
+        names.insert("default".to_string());
+    }
+    for star in &module.star_exports {
+        names.extend(
+            collect_exports(modules, star, seen)
+                .into_iter()
+                .filter(|name| name != "default"),
+        );
+    }
+    names
+}
+
+fn define(module: &Module) -> String {
+    let params = if module.cjs {
+        "module, exports, require"
+    } else {
+        "__arashi_module, __arashi_exports"
+    };
+    format!(
+        "__define(\"{}\", function ({}) {{\n{}\n}});\n",
+        module.id, params, module.code
+    )
+}
+
+fn entry_exports(id: &str, names: &BTreeSet<String>) -> String {
+    let mut code = format!("var __arashi_entry = __toESM(__require(\"{}\"));\n", id);
+    let mut specifiers = Vec::new();
+    for (i, name) in names.iter().enumerate() {
+        code.push_str(&format!(
+            "var __arashi_e{} = {};\n",
+            i,
+            member("__arashi_entry", name)
+        ));
+        specifiers.push(format!("__arashi_e{} as {}", i, property_key(name)));
+    }
+    if !specifiers.is_empty() {
+        code.push_str(&format!("export {{ {} }};\n", specifiers.join(", ")));
+    }
+    code
+}
+
+fn runtime_import(prefix: &str) -> String {
+    format!(
+        "import {{ __define, __require, __toESM, __export, __reexport, __preload }} from \"{}{}\";\n",
+        prefix, RUNTIME_FILE
+    )
+}
+
+/// 入口文件相对输出目录的前缀，react-dom/client.js 要用 ../ 才能找到 chunk
+fn relative_prefix(name: &str) -> String {
+    match name.matches('/').count() {
+        0 => "./".to_string(),
+        depth => "../".repeat(depth),
+    }
+}
+
+/// 去掉模块路径上的查询参数，src/logo.png?url => src/logo.png
+fn strip_query(path: &Path) -> PathBuf {
+    match path.to_str().and_then(|path| path.split_once('?')) {
+        Some((file, _)) => PathBuf::from(file),
+        None => path.to_path_buf(),
+    }
+}
+
+/// index.png => index-1a2b3c4d.png，内容变了名字才会变，浏览器可以一直缓存
+pub fn hashed_file_name(path: &Path, content: &[u8]) -> String {
+    let hash = format!("{:x}", Sha256::digest(content));
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("asset");
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => format!("{}-{}.{}", stem, &hash[..8], ext),
+        None => format!("{}-{}", stem, &hash[..8]),
+    }
+}
+
+/// import() 的 chunk 用模块的文件名，index.tsx 这种用目录名
+fn dynamic_chunk_name(path: &Path) -> String {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("chunk");
+    let name = if stem == "index" {
+        path.parent()
+            .and_then(|dir| dir.file_name())
+            .and_then(|s| s.to_str())
+            .unwrap_or(stem)
+    } else {
+        stem
+    };
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// 同一组入口共享的模块放在同一个 chunk 里，名字只和入口有关，每次构建都一样
+fn chunk_name(owners: &BTreeSet<&str>) -> String {
+    let mut hasher = Sha256::new();
+    for owner in owners {
+        hasher.update(owner.as_bytes());
+        hasher.update([0]);
+    }
+    format!("chunk-{}.js", &format!("{:x}", hasher.finalize())[..8])
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+}
+
+/// obj.name，名字不是合法标识符的时候用 obj["name"]
+fn member(object: &str, name: &str) -> String {
+    if is_identifier(name) {
+        format!("{}.{}", object, name)
+    } else {
+        format!(
+            "{}[{}]",
+            object,
+            serde_json::to_string(name).unwrap_or_default()
+        )
+    }
+}
+
+fn property_key(name: &str) -> String {
+    if is_identifier(name) {
+        name.to_string()
+    } else {
+        serde_json::to_string(name).unwrap_or_default()
+    }
+}
@@ -0,0 +1,390 @@
+use crate::plugins::Enforce;
+use crate::utils::error::Error;
+use crate::utils::fs;
+use oxc_transformer::ReplaceGlobalDefinesConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// 按顺序在项目根目录里找配置文件
+pub const CONFIG_FILES: [&str; 2] = ["arashi.config.json", "arashi.toml"];
+
+/// 项目配置，来自 arashi.config.json 或者 arashi.toml，字段名两种格式都用 camelCase。
+/// 命令行参数会覆盖配置文件里的值
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct Config {
+    /// index.html 所在的目录，相对配置文件所在的目录，加载完之后是绝对路径
+    pub root: PathBuf,
+    /// 部署的公共路径，比如 /app/，开发服务器会把请求路径里的它去掉
+    pub base: String,
+    /// 原样提供的静态资源目录，相对 root
+    pub public_dir: String,
+    /// 预构建依赖的缓存目录，相对 root
+    pub cache_dir: String,
+    pub server: ServerConfig,
+    pub resolve: ResolveConfig,
+    /// 全局常量替换，值是一段 js 表达式，比如 { "__VERSION__": "\"1.0.0\"" }，
+    /// 不是字符串的值会先转成 JSON
+    pub define: BTreeMap<String, serde_json::Value>,
+    pub jsx: JsxConfig,
+    pub css: CssConfig,
+    pub optimize_deps: OptimizeDepsConfig,
+    pub build: BuildConfig,
+    /// 外部插件，每个插件是一个通过 stdin / stdout 用 JSON-RPC 通信的子进程
+    pub plugins: Vec<ExternalPluginConfig>,
+    /// 实际加载的配置文件，没有配置文件的时候是 None
+    #[serde(skip)]
+    pub config_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    /// 转换之后的 ts / tsx 怎么带上 source map
+    pub sourcemap: SourcemapMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourcemapMode {
+    /// 以 data url 的形式放在代码最后
+    Inline,
+    /// 代码最后指向 xxx.tsx.map，由开发服务器提供
+    File,
+    /// 不生成
+    #[serde(rename = "none")]
+    Disabled,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct ResolveConfig {
+    /// 导入路径别名，比如 { "@": "/src" }，替换成的路径以 / 或者 ./ 开头时相对 root
+    pub alias: BTreeMap<String, String>,
+    /// exports / imports 额外匹配的条件，不写就用默认的 browser、import、module、development
+    pub conditions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct JsxConfig {
+    /// classic（React.createElement）或者 automatic（react/jsx-runtime）
+    pub runtime: JsxRuntimeKind,
+    /// automatic 模式从哪个包导入 jsx 函数，默认 react
+    pub import_source: Option<String>,
+    /// classic 模式用的函数，默认 React.createElement
+    pub pragma: Option<String>,
+    /// classic 模式的 Fragment，默认 React.Fragment
+    pub pragma_frag: Option<String>,
+    /// 生成带有文件位置信息的开发版 jsx 代码
+    pub development: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JsxRuntimeKind {
+    Classic,
+    Automatic,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct CssConfig {
+    /// *.module.css 的配置
+    pub modules: CssModulesConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct CssModulesConfig {
+    /// 局部类名的格式：[name] 是文件名，[local] 是原来的类名，[hash] 是文件路径的 hash
+    pub pattern: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct OptimizeDepsConfig {
+    /// 扫描不到但是需要预构建的依赖
+    pub include: Vec<String>,
+    /// 不参与启动时的预构建，请求到的时候再单独构建
+    pub exclude: Vec<String>,
+    /// 压缩预构建的依赖，默认不压缩，方便调试
+    pub minify: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct BuildConfig {
+    /// 构建产物的目录，相对 root
+    pub out_dir: String,
+    /// js、css、图片这些产物放在 outDir 下面的哪个目录
+    pub assets_dir: String,
+    /// 把这些包单独放到一个 chunk 里，比如 { "react-vendor": ["react", "react-dom"] }
+    pub manual_chunks: BTreeMap<String, Vec<String>>,
+    /// 压缩 js 产物，默认开启
+    pub minify: bool,
+    /// 小于这么多字节的资源内联成 base64 的 data url，0 表示不内联
+    pub assets_inline_limit: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ExternalPluginConfig {
+    /// 插件的名字，显示在日志和错误浮层上
+    pub name: String,
+    /// 启动插件的命令，在 root 下运行，比如 node
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// 每个请求等待的毫秒数，超时的话这个钩子算失败
+    #[serde(default = "default_plugin_timeout")]
+    pub timeout: u64,
+    /// pre 排在内置插件之前，默认排在之后
+    #[serde(default)]
+    pub enforce: Enforce,
+}
+
+fn default_plugin_timeout() -> u64 {
+    5000
+}
+
+impl ResolveConfig {
+    /// 用别名替换导入路径，没有匹配上返回 None。
+    /// 匹配整个路径或者路径的第一段（"@" 能匹配 "@/utils"，不能匹配 "@scope/pkg"），长的别名优先
+    pub fn apply_alias(&self, specifier: &str) -> Option<String> {
+        let (find, replacement) = self
+            .alias
+            .iter()
+            .filter(|(find, _)| {
+                specifier == find.as_str()
+                    || specifier
+                        .strip_prefix(find.as_str())
+                        .map(|rest| rest.starts_with('/'))
+                        .unwrap_or(false)
+            })
+            .max_by_key(|(find, _)| find.len())?;
+        // ./src 和 /src 都是相对 root 的，统一成 /src 这种浏览器能直接请求的路径
+        let replacement = match replacement.strip_prefix("./") {
+            Some(rest) => format!("/{}", rest),
+            None => replacement.clone(),
+        };
+        Some(format!("{}{}", replacement, &specifier[find.len()..]))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            root: PathBuf::from("."),
+            base: "/".to_string(),
+            public_dir: "public".to_string(),
+            cache_dir: "node_modules/.arashi".to_string(),
+            server: ServerConfig::default(),
+            resolve: ResolveConfig::default(),
+            define: BTreeMap::new(),
+            jsx: JsxConfig::default(),
+            css: CssConfig::default(),
+            optimize_deps: OptimizeDepsConfig::default(),
+            build: BuildConfig::default(),
+            plugins: Vec::new(),
+            config_file: None,
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            sourcemap: SourcemapMode::Inline,
+        }
+    }
+}
+
+impl Default for BuildConfig {
+    fn default() -> Self {
+        BuildConfig {
+            out_dir: "dist".to_string(),
+            assets_dir: "assets".to_string(),
+            manual_chunks: BTreeMap::new(),
+            minify: true,
+            assets_inline_limit: 4096,
+        }
+    }
+}
+
+impl Default for CssModulesConfig {
+    fn default() -> Self {
+        CssModulesConfig {
+            pattern: "[local]_[hash]".to_string(),
+        }
+    }
+}
+
+impl Default for JsxConfig {
+    fn default() -> Self {
+        JsxConfig {
+            runtime: JsxRuntimeKind::Classic,
+            import_source: None,
+            pragma: None,
+            pragma_frag: None,
+            development: false,
+        }
+    }
+}
+
+impl Config {
+    /// 在 dir 里找配置文件，config_file 是命令行指定的文件。
+    /// 都没有的话用默认配置，root 就是 dir
+    pub fn load(dir: &Path, config_file: Option<&Path>) -> Result<Config, Error> {
+        let file = match config_file {
+            Some(file) => Some(dir.join(file)),
+            None => CONFIG_FILES
+                .iter()
+                .map(|name| dir.join(name))
+                .find(|file| file.is_file()),
+        };
+        let mut config = match &file {
+            Some(file) => Self::parse_file(file)?,
+            None => Config::default(),
+        };
+        // root 相对配置文件所在的目录
+        let base_dir = file
+            .as_ref()
+            .and_then(|file| file.parent())
+            .unwrap_or(dir)
+            .to_path_buf();
+        config.config_file = file.map(|file| file.canonicalize().unwrap_or(file));
+        config.set_root(&base_dir.join(&config.root));
+        config.base = normalize_base(&config.base);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn parse_file(file: &Path) -> Result<Config, Error> {
+        let content = fs::read_file_content(file).map_err(|e| Error::io(file, e))?;
+        let parsed = if file.extension().map(|ext| ext == "toml").unwrap_or(false) {
+            toml::from_str(&content).map_err(|e| e.to_string())
+        } else {
+            serde_json::from_str(&content).map_err(|e| e.to_string())
+        };
+        parsed.map_err(|e| Error::config(Some(file), format!("格式错误: {}", e)))
+    }
+
+    /// 命令行的 --root 也走这里，统一转成绝对路径
+    pub fn set_root(&mut self, root: &Path) {
+        self.root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    }
+
+    pub fn set_base(&mut self, base: &str) {
+        self.base = normalize_base(base);
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let file = self.config_file.as_deref();
+        if !self.root.is_dir() {
+            return Err(Error::config(
+                file,
+                format!("root 目录不存在: {}", self.root.display()),
+            ));
+        }
+        // key 和值都要是合法的 js，错了的话在这里就报出来，不要等到转换的时候
+        if let Err(errors) = ReplaceGlobalDefinesConfig::new(&self.define_pairs()) {
+            let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            return Err(Error::config(
+                file,
+                format!("define 配置错误: {}", messages.join("; ")),
+            ));
+        }
+        Ok(())
+    }
+
+    /// 开发服务器监听的地址
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.server.host, self.server.port)
+    }
+
+    pub fn public_dir(&self) -> PathBuf {
+        self.root.join(&self.public_dir)
+    }
+
+    pub fn cache_dir(&self) -> PathBuf {
+        self.root.join(&self.cache_dir)
+    }
+
+    pub fn out_dir(&self) -> PathBuf {
+        self.root.join(&self.build.out_dir)
+    }
+
+    /// 构建产物在浏览器里的路径，比如 /app/assets/index-1a2b3c4d.js
+    /// 开发服务器上带 base 的路径，/@arashi/client => /app/@arashi/client
+    pub fn server_url(&self, path: &str) -> String {
+        format!("{}{}", self.base.trim_end_matches('/'), path)
+    }
+
+    pub fn asset_url(&self, file_name: &str) -> String {
+        format!(
+            "{}{}/{}",
+            self.base,
+            self.build.assets_dir.trim_matches('/'),
+            file_name
+        )
+    }
+
+    /// 用别名替换导入路径，没有匹配上返回 None
+    pub fn apply_alias(&self, specifier: &str) -> Option<String> {
+        self.resolve.apply_alias(specifier)
+    }
+
+    /// define 转成 (key, js 表达式)
+    pub fn define_pairs(&self) -> Vec<(String, String)> {
+        self.define
+            .iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    serde_json::Value::String(expr) => expr.clone(),
+                    other => other.to_string(),
+                };
+                (key.clone(), value)
+            })
+            .collect()
+    }
+
+    /// 打包依赖时 process.env.NODE_ENV 的值，可以用 define 改
+    pub fn node_env(&self) -> String {
+        self.define
+            .get("process.env.NODE_ENV")
+            .map(|value| match value {
+                serde_json::Value::String(expr) => expr.trim_matches(['"', '\'']).to_string(),
+                other => other.to_string(),
+            })
+            .unwrap_or_else(|| "development".to_string())
+    }
+
+    /// 影响预构建产物的配置，变了要重新预构建
+    pub fn deps_hash_input(&self) -> String {
+        serde_json::json!({
+            "conditions": self.resolve.conditions,
+            "alias": self.resolve.alias,
+            "nodeEnv": self.node_env(),
+            "include": self.optimize_deps.include,
+            "exclude": self.optimize_deps.exclude,
+            "minify": self.optimize_deps.minify,
+        })
+        .to_string()
+    }
+}
+
+/// base 统一成 /xxx/ 的形式
+fn normalize_base(base: &str) -> String {
+    let trimmed = base.trim_matches('/');
+    if trimmed.is_empty() {
+        "/".to_string()
+    } else {
+        format!("/{}/", trimmed)
+    }
+}
@@ -0,0 +1,527 @@
+use crate::utils::config::Config;
+use crate::utils::fs;
+use crate::utils::hmr::CLIENT_PATH;
+use crate::utils::resolver::{ResolveKind, Resolver};
+use crate::utils::transform::{code_frame, TransformError};
+use lightningcss::css_modules::{
+    self, CssModuleExport, CssModuleExports, CssModuleReference, Pattern,
+};
+use lightningcss::dependencies::{Dependency, DependencyOptions};
+use lightningcss::stylesheet::{ParserOptions, PrinterOptions, StyleSheet};
+use std::path::{Component, Path, PathBuf};
+
+/// css 里 url() 引用的文件怎么处理
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CssUrls {
+    /// 改成开发服务器上的路径，比如 /src/logo.png
+    Serve,
+    /// 构建：css 抽到单独的文件里，引用的文件作为模块的依赖导入，
+    /// url 先写成占位符，打包器输出带 hash 的文件之后再换成最终的 url
+    Extract,
+}
+
+/// css 转成的 js，watch_files 是 @import 进来的文件，它们变了结果也要跟着变，
+/// css 是 CssUrls::Extract 的时候抽出来的样式
+#[derive(Debug)]
+pub struct CssOutput {
+    pub code: String,
+    pub watch_files: Vec<PathBuf>,
+    pub css: Option<String>,
+}
+
+/// 构建时 url() 里的占位符，url 是引用的文件相对 root 的请求路径
+pub fn asset_placeholder(url: &str) -> String {
+    format!("__ARASHI_ASSET__{}__", url)
+}
+
+/// 出错的文件可能是 @import 进来的文件
+#[derive(Debug)]
+pub struct CssError {
+    pub file: PathBuf,
+    pub error: TransformError,
+}
+
+/// 导入的 css 转成插入 style 标签的 js：
+/// 1. @import 的文件内联进来，带 media / supports 条件的包在对应的 @media / @supports 里
+/// 2. url() 按 urls 改成开发服务器上的路径，或者构建时的占位符
+/// 3. *.module.css 的类名按 css.modules.pattern 改成局部的，默认导出 { 原来的类名: 改写之后的类名 }，
+///    是合法标识符的类名也会单独导出；composes 了其他文件的类名的话，先导入那个文件，类名拼在后面。
+///    普通的 css 默认导出 css 的内容，抽到单独文件里的时候是空字符串
+///
+/// css 作为 JSON 字符串放进 js，里面的反引号、${ 都不会影响生成的代码。
+/// hot 的时候 style 标签带着模块的 url 作为 id，重新执行的时候原地更新，dispose 的时候移除
+pub fn css_to_js(
+    source: &str,
+    file: &Path,
+    config: &Config,
+    urls: CssUrls,
+    hot: bool,
+) -> Result<CssOutput, CssError> {
+    let compiler = CssCompiler {
+        config,
+        resolver: Resolver::from_config(config),
+        urls,
+    };
+    let module = file.to_string_lossy().ends_with(".module.css");
+    let mut seen = vec![file.to_path_buf()];
+    let compiled = compiler.compile(source, file, module, &mut seen)?;
+
+    let mut code = String::new();
+    let id = compiler.url_of(file).filter(|_| hot);
+    let (style, extracted) = match urls {
+        CssUrls::Serve => (
+            inject_style(&js_string(&serve_urls(&compiled)), id.as_deref(), config),
+            None,
+        ),
+        CssUrls::Extract => (String::new(), Some(extract_urls(&compiled, &mut code))),
+    };
+    match compiled.exports {
+        Some(exports) => code.push_str(&module_exports(&style, exports)),
+        None => {
+            code.push_str(&style);
+            // 普通的 css 没有导出会变的东西，自己接受更新；
+            // CSS Modules 的类名可能变了，交给导入它的模块
+            if id.is_some() {
+                code.push_str("import.meta.hot.accept();\n");
+            }
+            match extracted {
+                Some(_) => code.push_str("export default \"\";\n"),
+                None => code.push_str("export default css;\n"),
+            }
+        }
+    }
+    Ok(CssOutput {
+        code,
+        watch_files: compiled.files,
+        css: extracted,
+    })
+}
+
+/// 构建时 <link rel="stylesheet"> 引用的 css：和 js 导入的 css 一样内联 @import，
+/// url() 引用的文件换成占位符。返回 (css, 引用的文件的请求路径)，由调用方输出这些文件再换掉占位符
+pub fn extract_stylesheet(
+    source: &str,
+    file: &Path,
+    config: &Config,
+) -> Result<(String, Vec<String>), CssError> {
+    let compiler = CssCompiler {
+        config,
+        resolver: Resolver::from_config(config),
+        urls: CssUrls::Extract,
+    };
+    let mut seen = vec![file.to_path_buf()];
+    let compiled = compiler.compile(source, file, false, &mut seen)?;
+    let css = extract_urls(&compiled, &mut String::new());
+    let mut urls = Vec::new();
+    for (_, target) in &compiled.urls {
+        if let UrlTarget::File { url, .. } = target {
+            if !urls.contains(url) {
+                urls.push(url.clone());
+            }
+        }
+    }
+    Ok((css, urls))
+}
+
+/// 一个 css 文件编译的结果，@import 的文件已经内联进来了，url() 还是占位符
+struct CompiledCss {
+    css: String,
+    /// (占位符, 引用的文件)
+    urls: Vec<(String, UrlTarget)>,
+    exports: Option<CssModuleExports>,
+    /// @import 进来的文件
+    files: Vec<PathBuf>,
+}
+
+enum UrlTarget {
+    /// data:、http: 或者开发服务器上找不到的文件，保持原样
+    Keep(String),
+    /// 项目里的文件：相对 root 的请求路径，后面的 ?query 或者 #hash
+    File { url: String, suffix: String },
+}
+
+struct CssCompiler<'c> {
+    config: &'c Config,
+    resolver: Resolver,
+    urls: CssUrls,
+}
+
+impl CssCompiler<'_> {
+    /// seen 是已经内联过的文件，循环 @import 或者被 @import 了多次的文件只内联一次
+    fn compile(
+        &self,
+        source: &str,
+        file: &Path,
+        module: bool,
+        seen: &mut Vec<PathBuf>,
+    ) -> Result<CompiledCss, CssError> {
+        let css_modules = match module {
+            true => Some(css_modules::Config {
+                pattern: Pattern::parse(&self.config.css.modules.pattern).map_err(|e| {
+                    css_error(file, source, format!("css.modules.pattern: {}", e), None)
+                })?,
+                ..Default::default()
+            }),
+            false => None,
+        };
+        let options = ParserOptions {
+            // [hash] 是这个名字的 hash，用相对 root 的路径，在哪台机器上构建都一样
+            filename: self
+                .url_of(file)
+                .unwrap_or_else(|| file.to_string_lossy().to_string()),
+            css_modules,
+            ..Default::default()
+        };
+        let stylesheet = StyleSheet::parse(source, options).map_err(|e| {
+            // lightningcss 的行号从 0 开始，列号从 1 开始
+            let position = e
+                .loc
+                .map(|loc| (loc.line as usize + 1, loc.column as usize));
+            css_error(file, source, e.kind.to_string(), position)
+        })?;
+        let result = stylesheet
+            .to_css(PrinterOptions {
+                analyze_dependencies: Some(DependencyOptions {
+                    remove_imports: true,
+                }),
+                ..Default::default()
+            })
+            .map_err(|e| {
+                let position = e
+                    .loc
+                    .map(|loc| (loc.line as usize + 1, loc.column as usize));
+                css_error(file, source, e.kind.to_string(), position)
+            })?;
+
+        let mut compiled = CompiledCss {
+            css: String::new(),
+            urls: Vec::new(),
+            exports: result.exports,
+            files: Vec::new(),
+        };
+        // 外部的 @import 必须在最前面，然后是内联进来的文件，最后是文件自己的内容
+        let mut external_imports = String::new();
+        let mut imports = String::new();
+        for dependency in result.dependencies.unwrap_or_default() {
+            match dependency {
+                Dependency::Import(import) => {
+                    if is_external(&import.url) {
+                        let conditions = [import.supports.as_deref(), import.media.as_deref()];
+                        let rule = std::iter::once(css_string(&import.url))
+                            .chain(conditions.into_iter().flatten().map(str::to_string))
+                            .collect::<Vec<_>>()
+                            .join(" ");
+                        external_imports.push_str(&format!("@import {};\n", rule));
+                        continue;
+                    }
+                    let imported = self.resolve_import(&import.url, file).ok_or_else(|| {
+                        let position = (
+                            import.loc.start.line as usize,
+                            import.loc.start.column as usize,
+                        );
+                        css_error(
+                            file,
+                            source,
+                            format!("cannot resolve \"{}\"", import.url),
+                            Some(position),
+                        )
+                    })?;
+                    if seen.contains(&imported) {
+                        continue;
+                    }
+                    seen.push(imported.clone());
+                    let imported_source = fs::read_file_content(&imported).map_err(|e| {
+                        css_error(file, source, format!("{}: {}", imported.display(), e), None)
+                    })?;
+                    let inner = self.compile(&imported_source, &imported, false, seen)?;
+
+                    let mut css = inner.css;
+                    if let Some(media) = &import.media {
+                        css = format!("@media {} {{\n{}}}\n", media, css);
+                    }
+                    if let Some(supports) = &import.supports {
+                        css = format!("@supports {} {{\n{}}}\n", supports, css);
+                    }
+                    imports.push_str(&css);
+                    compiled.urls.extend(inner.urls);
+                    compiled.files.push(imported);
+                    compiled.files.extend(inner.files);
+                }
+                Dependency::Url(url) => {
+                    let target = self.resolve_url(&url.url, file).map_err(|message| {
+                        let position = (url.loc.start.line as usize, url.loc.start.column as usize);
+                        css_error(file, source, message, Some(position))
+                    })?;
+                    compiled.urls.push((url.placeholder, target));
+                }
+            }
+        }
+        compiled.css = format!("{}{}{}", external_imports, imports, result.code);
+        Ok(compiled)
+    }
+
+    /// @import "x.css" 先按相对路径找，找不到再当成别名或者包里的文件
+    fn resolve_import(&self, specifier: &str, file: &Path) -> Option<PathBuf> {
+        let dir = file.parent().unwrap_or(&self.config.root);
+        let relative = dir.join(specifier);
+        let resolved = if !specifier.starts_with('/') && relative.is_file() {
+            relative
+        } else {
+            self.resolver.resolve(specifier, dir, ResolveKind::Import)?
+        };
+        Some(normalize(&resolved))
+    }
+
+    /// url() 里的路径都是相对当前 css 文件的，/ 开头的相对 root，也可以用别名。
+    /// 不在 root 里的文件开发服务器提供不了，保持原样。
+    /// 找不到的文件开发服务器上只警告（可能马上就加上了），构建时直接报错
+    fn resolve_url(&self, url: &str, file: &Path) -> Result<UrlTarget, String> {
+        if is_external(url) || url.starts_with('#') {
+            return Ok(UrlTarget::Keep(url.to_string()));
+        }
+        let (path, suffix) = url.split_at(url.find(['?', '#']).unwrap_or(url.len()));
+        let aliased = self.config.apply_alias(path);
+        let path = aliased.as_deref().unwrap_or(path);
+        let resolved = match path.strip_prefix('/') {
+            Some(path) => self.config.root.join(path),
+            None => file.parent().unwrap_or(&self.config.root).join(path),
+        };
+        match self.url_of(&normalize(&resolved)) {
+            Some(served) if resolved.is_file() => Ok(UrlTarget::File {
+                url: served,
+                suffix: suffix.to_string(),
+            }),
+            Some(_) if self.urls == CssUrls::Extract => Err(format!("cannot resolve \"{}\"", url)),
+            Some(_) => {
+                println!("[css] {} 里的 url({}) 找不到文件", file.display(), url);
+                Ok(UrlTarget::Keep(url.to_string()))
+            }
+            None => Ok(UrlTarget::Keep(url.to_string())),
+        }
+    }
+
+    /// 相对 root 的请求路径
+    fn url_of(&self, file: &Path) -> Option<String> {
+        let relative = file.strip_prefix(&self.config.root).ok()?;
+        Some(format!(
+            "/{}",
+            relative.to_string_lossy().replace('\\', "/")
+        ))
+    }
+}
+
+/// 开发服务器：占位符换成请求路径
+fn serve_urls(compiled: &CompiledCss) -> String {
+    let mut css = compiled.css.clone();
+    for (placeholder, target) in &compiled.urls {
+        let url = match target {
+            UrlTarget::File { url, suffix } => format!("{}{}", url, suffix),
+            UrlTarget::Keep(url) => url.clone(),
+        };
+        css = css.replace(placeholder, &escape_css_string(&url));
+    }
+    css
+}
+
+/// 构建：引用的文件作为依赖导入，打包器才会输出它们，url 换成占位符
+fn extract_urls(compiled: &CompiledCss, code: &mut String) -> String {
+    let mut css = compiled.css.clone();
+    let mut imported = Vec::new();
+    for (placeholder, target) in &compiled.urls {
+        let url = match target {
+            UrlTarget::File { url, suffix } => {
+                if !imported.contains(url) {
+                    code.push_str(&format!("import {};\n", js_string(url)));
+                    imported.push(url.clone());
+                }
+                format!("{}{}", asset_placeholder(url), suffix)
+            }
+            UrlTarget::Keep(url) => url.clone(),
+        };
+        css = css.replace(placeholder, &escape_css_string(&url));
+    }
+    css
+}
+
+/// *.module.css 的导出，style 是插入 style 标签的代码，css 抽出去了的话是空的
+fn module_exports(style: &str, exports: CssModuleExports) -> String {
+    let mut exports: Vec<_> = exports.into_iter().collect();
+    exports.sort_by(|a, b| a.0.cmp(&b.0));
+    // composes 用到的其他文件，按出现的顺序导入
+    let mut dependencies: Vec<String> = Vec::new();
+    let mut declarations = String::new();
+    let mut classes = Vec::new();
+    let mut named = Vec::new();
+    for (index, (local, export)) in exports.iter().enumerate() {
+        let mut parts = vec![js_string(&export.name)];
+        composed_classes(&exports, export, &mut dependencies, &mut parts);
+        declarations.push_str(&format!(
+            "const __arashi_class_{} = {};\n",
+            index,
+            parts.join(" + \" \" + ")
+        ));
+        classes.push(format!(
+            "  {}: __arashi_class_{},\n",
+            js_string(local),
+            index
+        ));
+        if is_identifier(local) {
+            named.push(format!("__arashi_class_{} as {}", index, local));
+        }
+    }
+
+    let mut code = String::new();
+    for (index, specifier) in dependencies.iter().enumerate() {
+        code.push_str(&format!(
+            "import __arashi_composes_{} from {};\n",
+            index,
+            js_string(specifier)
+        ));
+    }
+    code.push_str(style);
+    code.push_str(&declarations);
+    code.push_str(&format!("export default {{\n{}}};\n", classes.concat()));
+    if !named.is_empty() {
+        code.push_str(&format!("export {{ {} }};\n", named.join(", ")));
+    }
+    code
+}
+
+/// composes 进来的类名，同一个文件里的类名自己也 composes 了的话一起带上
+fn composed_classes(
+    exports: &[(String, CssModuleExport)],
+    export: &CssModuleExport,
+    dependencies: &mut Vec<String>,
+    parts: &mut Vec<String>,
+) {
+    for reference in &export.composes {
+        let part = match reference {
+            CssModuleReference::Local { name } => {
+                let part = js_string(name);
+                if parts.contains(&part) {
+                    continue;
+                }
+                parts.push(part);
+                if let Some((_, composed)) = exports.iter().find(|(_, e)| &e.name == name) {
+                    composed_classes(exports, composed, dependencies, parts);
+                }
+                continue;
+            }
+            CssModuleReference::Global { name } => js_string(name),
+            CssModuleReference::Dependency { name, specifier } => {
+                let dep = match dependencies.iter().position(|dep| dep == specifier) {
+                    Some(dep) => dep,
+                    None => {
+                        dependencies.push(specifier.clone());
+                        dependencies.len() - 1
+                    }
+                };
+                format!("__arashi_composes_{}[{}]", dep, js_string(name))
+            }
+        };
+        if !parts.contains(&part) {
+            parts.push(part);
+        }
+    }
+}
+
+/// 把 css 放到 style 标签里插到 head 上，css 是 js 表达式，在代码里的变量名是 css。
+/// 有 id 的话交给客户端的 updateStyle，同一个 id 只有一个 style 标签
+fn inject_style(css: &str, id: Option<&str>, config: &Config) -> String {
+    match id {
+        Some(id) => format!(
+            "import {{ updateStyle as __arashi__updateStyle, removeStyle as __arashi__removeStyle }} from \"{}\";\n\
+             const __arashi__id = {};\n\
+             const css = {};\n\
+             __arashi__updateStyle(__arashi__id, css);\n\
+             import.meta.hot.dispose(() => __arashi__removeStyle(__arashi__id));\n",
+            config.server_url(CLIENT_PATH),
+            js_string(id),
+            css
+        ),
+        None => format!(
+            "const css = {};\n\
+             const style = document.createElement('style');\n\
+             style.textContent = css;\n\
+             document.head.appendChild(style);\n",
+            css
+        ),
+    }
+}
+
+/// 浏览器通过 <link rel="stylesheet"> 请求的 css 要原样返回，不能转成 js
+pub fn is_stylesheet_request<State>(req: &tide::Request<State>) -> bool {
+    let header = |name: &str| {
+        req.header(name)
+            .map(|values| values.as_str().to_string())
+            .unwrap_or_default()
+    };
+    header("Sec-Fetch-Dest") == "style" || header("Accept").starts_with("text/css")
+}
+
+fn css_error(
+    file: &Path,
+    source: &str,
+    message: String,
+    position: Option<(usize, usize)>,
+) -> CssError {
+    CssError {
+        file: file.to_path_buf(),
+        error: TransformError {
+            message,
+            line: position.map(|(line, _)| line),
+            column: position.map(|(_, column)| column),
+            frame: position.map(|(line, column)| code_frame(source, line, column)),
+        },
+    }
+}
+
+fn is_external(url: &str) -> bool {
+    url.starts_with("data:")
+        || url.starts_with("http:")
+        || url.starts_with("https:")
+        || url.starts_with("//")
+}
+
+/// 去掉路径里的 . 和 ..，同一个文件只有一种写法
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+fn js_string(value: &str) -> String {
+    serde_json::Value::String(value.to_string()).to_string()
+}
+
+/// 放进 css 的双引号字符串里
+fn escape_css_string(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\a ")
+}
+
+fn css_string(value: &str) -> String {
+    format!("\"{}\"", escape_css_string(value))
+}
+
+/// 能单独导出的类名，default 已经是整个对象了
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    let valid_start = chars
+        .next()
+        .map(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        .unwrap_or(false);
+    valid_start
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+        && name != "default"
+}
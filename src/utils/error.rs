@@ -0,0 +1,169 @@
+use crate::utils::hmr::{ErrorLocation, ErrorPayload};
+use crate::utils::transform::TransformError;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use tide::{Response, StatusCode};
+
+/// 开发服务器处理请求、打包和构建时可能出的错。
+/// 每个请求的错误只影响这个请求：在中间件里转成 404 / 500 返回，同时显示在浏览器的错误浮层上
+#[derive(Debug)]
+pub enum Error {
+    /// 导入的模块找不到
+    Resolve { specifier: String, importer: String },
+    /// 源码转换失败，plugin 是出错的那一步，比如 arashi:typescript
+    Transform {
+        plugin: &'static str,
+        file: PathBuf,
+        error: TransformError,
+    },
+    /// 读写文件失败
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// 依赖预构建失败
+    DepBuild { name: String, message: String },
+    /// 生产构建失败，file 是出错的文件
+    Build { file: PathBuf, message: String },
+    /// 配置文件格式错误或者配置的值不合法，命令行参数错了的话 file 是 None
+    Config {
+        file: Option<PathBuf>,
+        message: String,
+    },
+    /// 插件的钩子失败，比如外部插件超时或者进程退出
+    Plugin {
+        plugin: &'static str,
+        message: String,
+    },
+}
+
+impl Error {
+    pub fn io(path: impl Into<PathBuf>, source: std::io::Error) -> Self {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    pub fn dep_build(name: &str, message: impl fmt::Display) -> Self {
+        Error::DepBuild {
+            name: name.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn build(file: impl Into<PathBuf>, message: impl fmt::Display) -> Self {
+        Error::Build {
+            file: file.into(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn config(file: Option<&Path>, message: impl fmt::Display) -> Self {
+        Error::Config {
+            file: file.map(Path::to_path_buf),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn plugin_error(plugin: &'static str, message: impl fmt::Display) -> Self {
+        Error::Plugin {
+            plugin,
+            message: message.to_string(),
+        }
+    }
+
+    /// 找不到的是 404，其他都是 500
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::Resolve { .. } => StatusCode::NotFound,
+            Error::Io { source, .. } if source.kind() == std::io::ErrorKind::NotFound => {
+                StatusCode::NotFound
+            }
+            _ => StatusCode::InternalServerError,
+        }
+    }
+
+    fn plugin(&self) -> &'static str {
+        match self {
+            Error::Resolve { .. } => "arashi:resolve",
+            Error::Transform { plugin, .. } => plugin,
+            Error::Io { .. } => "arashi:load",
+            Error::DepBuild { .. } => "arashi:deps",
+            Error::Build { .. } => "arashi:build",
+            Error::Config { .. } => "arashi:config",
+            Error::Plugin { plugin, .. } => plugin,
+        }
+    }
+
+    /// 发给浏览器的错误信息，id 是出错的请求路径
+    pub fn to_payload(&self, id: &str) -> ErrorPayload {
+        let (message, loc, frame) = match self {
+            Error::Transform { file, error, .. } => (
+                error.message.clone(),
+                Some(ErrorLocation {
+                    file: file.to_string_lossy().to_string(),
+                    line: error.line,
+                    column: error.column,
+                }),
+                error.frame.clone(),
+            ),
+            Error::Build { file, message } => (
+                message.clone(),
+                Some(ErrorLocation {
+                    file: file.to_string_lossy().to_string(),
+                    line: None,
+                    column: None,
+                }),
+                None,
+            ),
+            Error::Io { path, source } => (
+                source.to_string(),
+                Some(ErrorLocation {
+                    file: path.to_string_lossy().to_string(),
+                    line: None,
+                    column: None,
+                }),
+                None,
+            ),
+            other => (other.to_string(), None, None),
+        };
+        ErrorPayload {
+            message,
+            plugin: self.plugin().to_string(),
+            id: id.to_string(),
+            loc,
+            frame,
+        }
+    }
+
+    pub fn into_response(self, id: &str) -> Response {
+        self.to_payload(id).into_response(self.status())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Resolve {
+                specifier,
+                importer,
+            } => write!(f, "cannot resolve \"{}\" from {}", specifier, importer),
+            Error::Transform { file, error, .. } => write!(f, "{}: {}", file.display(), error),
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::DepBuild { name, message } => write!(f, "构建依赖 {} 失败: {}", name, message),
+            Error::Build { file, message } => write!(f, "{}: {}", file.display(), message),
+            Error::Config {
+                file: Some(file),
+                message,
+            } => write!(f, "{}: {}", file.display(), message),
+            Error::Config {
+                file: None,
+                message,
+            } => write!(f, "{}", message),
+            Error::Plugin { plugin, message } => write!(f, "{}: {}", plugin, message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,419 @@
+use crate::utils::config::Config;
+use crate::utils::sourcemap::CodeEdits;
+use crate::utils::transform::{code_frame, line_column, TransformError};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use oxc_allocator::Allocator;
+use oxc_ast::ast::{
+    Argument, ArrayExpressionElement, CallExpression, Expression, ObjectExpression,
+    ObjectPropertyKind,
+};
+use oxc_ast::visit::walk;
+use oxc_ast::Visit;
+use oxc_parser::{Parser, ParserReturn};
+use oxc_span::{SourceType, Span};
+use std::path::{Path, PathBuf};
+
+/// import.meta.glob 展开之后的改动，dirs 是匹配的目录，里面增删文件之后要重新展开
+pub struct GlobExpansion {
+    pub edits: CodeEdits,
+    pub dirs: Vec<PathBuf>,
+}
+
+/// import.meta.glob(patterns, options) 的参数，都必须是字面量
+#[derive(Debug, Default)]
+struct GlobCall {
+    span: Span,
+    patterns: Vec<String>,
+    eager: bool,
+    /// 只要模块的这个导出，"default"、"setup" 之类，"*" 或者没有就是整个模块
+    import: Option<String>,
+    /// 加在每个导入路径后面的查询参数，比如 ?raw
+    query: String,
+}
+
+/// 一个 glob 模式解析成了相对项目根目录、以 / 开头的模式
+struct Pattern {
+    negative: bool,
+    /// 以 ./ 或 ../ 开头，第一个模式是的话键和导入路径用相对导入者的路径（./pages/a.tsx），
+    /// 否则用根路径（/src/pages/a.tsx）
+    relative: bool,
+    glob: String,
+}
+
+/// 把 code 里的 import.meta.glob(...) 展开成对象字面量：
+/// - 默认是 { "./pages/a.tsx": () => import("./pages/a.tsx") }，用到的时候才加载
+/// - eager: true 在模块开头静态导入，对象的值直接是模块
+/// - import: "setup" 只取模块的某个导出，query: "?raw" 或 { raw: true } 加在导入路径后面
+/// - 以 ! 开头的模式排除匹配到的文件
+///
+/// url 是导入者的 url（/src/main.tsx），没有 import.meta.glob 的话返回 None
+pub fn expand_glob_imports(
+    code: &str,
+    url: &str,
+    config: &Config,
+) -> Result<Option<GlobExpansion>, TransformError> {
+    if !code.contains("import.meta.glob") {
+        return Ok(None);
+    }
+    let allocator = Allocator::default();
+    let ParserReturn {
+        program,
+        errors,
+        panicked,
+        ..
+    } = Parser::new(&allocator, code, SourceType::mjs()).parse();
+    // 语法错误交给后面的导入分析报告
+    if panicked || !errors.is_empty() {
+        return Ok(None);
+    }
+    let mut visitor = GlobVisitor {
+        calls: Vec::new(),
+        error: None,
+    };
+    visitor.visit_program(&program);
+    if let Some((span, message)) = visitor.error {
+        return Err(error_at(code, span, message));
+    }
+    if visitor.calls.is_empty() {
+        return Ok(None);
+    }
+
+    let importer = config.root.join(url.trim_start_matches('/'));
+    let mut edits = CodeEdits::new();
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    let mut header = String::new();
+    for (index, call) in visitor.calls.iter().enumerate() {
+        let patterns = call
+            .patterns
+            .iter()
+            .map(|pattern| resolve_pattern(pattern, url, config))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|message| error_at(code, call.span, message))?;
+        let (positive, negative): (Vec<&Pattern>, Vec<&Pattern>) =
+            patterns.iter().partition(|pattern| !pattern.negative);
+        if positive.is_empty() {
+            return Err(error_at(
+                code,
+                call.span,
+                "import.meta.glob 至少要有一个不以 ! 开头的模式".to_string(),
+            ));
+        }
+        let included = glob_set(&positive).map_err(|e| error_at(code, call.span, e))?;
+        let excluded = glob_set(&negative).map_err(|e| error_at(code, call.span, e))?;
+
+        let mut files = Vec::new();
+        for pattern in &positive {
+            let (dir, recursive) = base_dir(&pattern.glob);
+            let dir = config.root.join(dir.trim_start_matches('/'));
+            collect_files(&dir, recursive, &mut files);
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+        files.sort();
+        files.dedup();
+
+        let mut entries = Vec::new();
+        for file in files {
+            let Some(file_url) = root_url(&file, config) else {
+                continue;
+            };
+            if file == importer || !included.is_match(&file_url) || excluded.is_match(&file_url) {
+                continue;
+            }
+            let key = match positive[0].relative {
+                true => relative_url(url, &file_url),
+                false => file_url,
+            };
+            let specifier =
+                serde_json::to_string(&format!("{}{}", key, call.query)).unwrap_or_default();
+            let value = if call.eager {
+                let name = format!("__arashi_glob_{}_{}", index, entries.len());
+                let binding = match call.import.as_deref() {
+                    None | Some("*") => format!("* as {}", name),
+                    Some("default") => name.clone(),
+                    Some(export) => format!(
+                        "{{ {} as {} }}",
+                        serde_json::to_string(export).unwrap_or_default(),
+                        name
+                    ),
+                };
+                header.push_str(&format!("import {} from {};\n", binding, specifier));
+                name
+            } else {
+                match call.import.as_deref() {
+                    None | Some("*") => format!("() => import({})", specifier),
+                    Some(export) => format!(
+                        "() => import({}).then((m) => m[{}])",
+                        specifier,
+                        serde_json::to_string(export).unwrap_or_default()
+                    ),
+                }
+            };
+            entries.push(format!(
+                "{}: {}",
+                serde_json::to_string(&key).unwrap_or_default(),
+                value
+            ));
+        }
+        edits.replace(
+            call.span.start as usize,
+            call.span.end as usize,
+            format!("{{{}}}", entries.join(", ")),
+        );
+    }
+    if !header.is_empty() {
+        edits.insert(0, header);
+    }
+    Ok(Some(GlobExpansion { edits, dirs }))
+}
+
+struct GlobVisitor {
+    calls: Vec<GlobCall>,
+    /// 参数不对的第一个调用：位置和原因
+    error: Option<(Span, String)>,
+}
+
+impl<'a> Visit<'a> for GlobVisitor {
+    fn visit_call_expression(&mut self, expr: &CallExpression<'a>) {
+        if self.error.is_none() && is_import_meta_glob(&expr.callee) {
+            match parse_call(expr) {
+                Ok(call) => self.calls.push(call),
+                Err(message) => self.error = Some((expr.span, message)),
+            }
+        }
+        walk::walk_call_expression(self, expr);
+    }
+}
+
+fn is_import_meta_glob(callee: &Expression) -> bool {
+    let Expression::StaticMemberExpression(glob) = callee else {
+        return false;
+    };
+    let Expression::MetaProperty(meta) = &glob.object else {
+        return false;
+    };
+    glob.property.name == "glob" && meta.meta.name == "import" && meta.property.name == "meta"
+}
+
+fn parse_call(expr: &CallExpression) -> Result<GlobCall, String> {
+    let mut call = GlobCall {
+        span: expr.span,
+        ..GlobCall::default()
+    };
+    match expr.arguments.first() {
+        Some(Argument::ArrayExpression(patterns)) => {
+            for element in &patterns.elements {
+                match element {
+                    ArrayExpressionElement::StringLiteral(pattern) => {
+                        call.patterns.push(pattern.value.to_string())
+                    }
+                    _ => return Err("import.meta.glob 的模式必须是字符串字面量".to_string()),
+                }
+            }
+        }
+        Some(argument) => match argument.as_expression().and_then(string_literal) {
+            Some(pattern) => call.patterns.push(pattern),
+            None => return Err("import.meta.glob 的模式必须是字符串字面量".to_string()),
+        },
+        None => return Err("import.meta.glob 缺少模式".to_string()),
+    }
+    match expr.arguments.get(1) {
+        Some(Argument::ObjectExpression(options)) => parse_options(options, &mut call)?,
+        Some(_) => return Err("import.meta.glob 的选项必须是对象字面量".to_string()),
+        None => {}
+    }
+    Ok(call)
+}
+
+fn parse_options(options: &ObjectExpression, call: &mut GlobCall) -> Result<(), String> {
+    for property in &options.properties {
+        let ObjectPropertyKind::ObjectProperty(property) = property else {
+            return Err("import.meta.glob 的选项不能展开".to_string());
+        };
+        let name = property.key.static_name().unwrap_or_default();
+        match (name.as_ref(), &property.value) {
+            ("eager", Expression::BooleanLiteral(eager)) => call.eager = eager.value,
+            ("import", value) => match string_literal(value) {
+                Some(import) => call.import = Some(import),
+                None => return Err("import.meta.glob 的 import 必须是字符串".to_string()),
+            },
+            ("query", Expression::ObjectExpression(query)) => {
+                call.query = query_string(query)?;
+            }
+            ("query", value) => match string_literal(value) {
+                Some(query) if query.is_empty() || query.starts_with('?') => call.query = query,
+                Some(query) => call.query = format!("?{}", query),
+                None => return Err("import.meta.glob 的 query 必须是字符串或者对象".to_string()),
+            },
+            ("eager", _) => {
+                return Err("import.meta.glob 的 eager 必须是 true 或 false".to_string())
+            }
+            (name, _) => return Err(format!("import.meta.glob 不支持选项 {}", name)),
+        }
+    }
+    Ok(())
+}
+
+/// { raw: true, lang: "ts" } => ?raw&lang=ts
+fn query_string(query: &ObjectExpression) -> Result<String, String> {
+    let mut params = Vec::new();
+    for property in &query.properties {
+        let ObjectPropertyKind::ObjectProperty(property) = property else {
+            return Err("import.meta.glob 的 query 不能展开".to_string());
+        };
+        let name = property.key.static_name().unwrap_or_default();
+        let param = match &property.value {
+            Expression::BooleanLiteral(value) if value.value => name.to_string(),
+            Expression::BooleanLiteral(_) => continue,
+            Expression::NumericLiteral(value) => format!("{}={}", name, value.value),
+            value => match string_literal(value) {
+                Some(value) => format!("{}={}", name, value),
+                None => return Err("import.meta.glob 的 query 的值必须是字面量".to_string()),
+            },
+        };
+        params.push(param);
+    }
+    Ok(match params.is_empty() {
+        true => String::new(),
+        false => format!("?{}", params.join("&")),
+    })
+}
+
+/// "xxx" 或者没有插值的 `xxx`
+fn string_literal(expr: &Expression) -> Option<String> {
+    match expr {
+        Expression::StringLiteral(literal) => Some(literal.value.to_string()),
+        Expression::TemplateLiteral(literal) if literal.expressions.is_empty() => literal
+            .quasis
+            .first()
+            .and_then(|quasi| quasi.value.cooked.as_ref())
+            .map(|cooked| cooked.to_string()),
+        _ => None,
+    }
+}
+
+/// ./pages/*.tsx、../x/*.ts、/src/*.ts 和别名开头的模式解析成以 / 开头、相对项目根目录的模式
+fn resolve_pattern(pattern: &str, url: &str, config: &Config) -> Result<Pattern, String> {
+    let (negative, pattern) = match pattern.strip_prefix('!') {
+        Some(pattern) => (true, pattern),
+        None => (false, pattern),
+    };
+    let relative = pattern.starts_with("./") || pattern.starts_with("../");
+    let glob = if relative {
+        let dir = url.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+        normalize(&format!("{}/{}", dir, pattern))
+    } else if pattern.starts_with('/') {
+        normalize(pattern)
+    } else {
+        match config.apply_alias(pattern) {
+            Some(aliased) if aliased.starts_with('/') => normalize(&aliased),
+            _ => {
+                return Err(format!(
+                    "import.meta.glob 的模式要以 ./、../、/ 或者别名开头: {}",
+                    pattern
+                ))
+            }
+        }
+    };
+    Ok(Pattern {
+        negative,
+        relative,
+        glob,
+    })
+}
+
+/// 去掉路径里的 . 和 ..，不会超出项目根目录
+fn normalize(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    format!("/{}", segments.join("/"))
+}
+
+fn glob_set(patterns: &[&Pattern]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        // * 不能跨目录，** 才可以
+        let glob = GlobBuilder::new(&pattern.glob)
+            .literal_separator(true)
+            .build()
+            .map_err(|e| format!("import.meta.glob 的模式不对: {}", e))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|e| format!("import.meta.glob 的模式不对: {}", e))
+}
+
+/// 模式里第一个带通配符的部分之前的目录，后面还有 / 的话要递归查找：
+/// /src/pages/*.tsx => (/src/pages, false)，/src/**/index.ts => (/src, true)
+fn base_dir(glob: &str) -> (String, bool) {
+    let segments: Vec<&str> = glob.split('/').collect();
+    let wildcard = segments
+        .iter()
+        .position(|segment| segment.contains(['*', '?', '[', '{']))
+        .unwrap_or(segments.len() - 1);
+    (
+        segments[..wildcard].join("/"),
+        wildcard + 1 < segments.len(),
+    )
+}
+
+/// 目录下的文件，跳过 node_modules 和 . 开头的目录
+fn collect_files(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if recursive && name != "node_modules" && !name.starts_with('.') {
+                collect_files(&path, recursive, files);
+            }
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+/// 文件相对项目根目录的 url，/src/pages/a.tsx
+fn root_url(file: &Path, config: &Config) -> Option<String> {
+    let relative = file.strip_prefix(&config.root).ok()?;
+    Some(format!(
+        "/{}",
+        relative.to_string_lossy().replace('\\', "/")
+    ))
+}
+
+/// /src/main.tsx 导入 /src/pages/a.tsx => ./pages/a.tsx
+fn relative_url(importer: &str, target: &str) -> String {
+    let from: Vec<&str> = importer.split('/').filter(|s| !s.is_empty()).collect();
+    let from = &from[..from.len().saturating_sub(1)];
+    let to: Vec<&str> = target.split('/').filter(|s| !s.is_empty()).collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut parts = vec![".."; from.len() - common];
+    parts.extend(&to[common..]);
+    match from.len() == common {
+        true => format!("./{}", parts.join("/")),
+        false => parts.join("/"),
+    }
+}
+
+fn error_at(code: &str, span: Span, message: String) -> TransformError {
+    let (line, column) = line_column(code, span.start as usize);
+    TransformError {
+        message,
+        line: Some(line),
+        column: Some(column),
+        frame: Some(code_frame(code, line, column)),
+    }
+}
@@ -0,0 +1,418 @@
+use crate::plugins::{HotUpdate, PluginContainer};
+use crate::utils::config::Config;
+use crate::utils::module_graph::{HmrBoundary, ModuleGraph};
+use crate::utils::sourcemap::CodeEdits;
+use crate::utils::watcher::{WatchEvent, WatchEventKind, WatchListener};
+use async_std::stream::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tide::{Response, StatusCode};
+use tide_websockets::{Message, WebSocketConnection};
+use tokio::sync::RwLock;
+
+/// 注入到 index.html 的客户端运行时
+pub const CLIENT_SCRIPT: &str = include_str!("../client/client.js");
+/// 下面这些路径在页面和模块里都要带上 base，用 Config::server_url
+pub const CLIENT_PATH: &str = "/@arashi/client";
+pub const HMR_PATH: &str = "/__arashi_hmr";
+pub const REACT_REFRESH_PATH: &str = "/@modules/react-refresh/runtime";
+
+/// 服务端推送给浏览器的消息，和 client.js 里的 handleMessage 一一对应
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum HmrPayload {
+    Connected,
+    Update { updates: Vec<HmrUpdate> },
+    FullReload { path: Option<String> },
+    Error { err: ErrorPayload },
+}
+
+/// 转换、解析失败的详细信息，浏览器用它显示错误浮层
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorPayload {
+    pub message: String,
+    /// 哪一步出的错，比如 arashi:typescript、arashi:css、arashi:resolve
+    pub plugin: String,
+    /// 出错的模块 url
+    pub id: String,
+    pub loc: Option<ErrorLocation>,
+    /// 出错位置附近的源码
+    pub frame: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorLocation {
+    pub file: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl ErrorPayload {
+    /// 出错的请求返回错误信息的纯文本，错误本身挂在 response 上，
+    /// 由 HmrClient 统一发给浏览器
+    pub fn into_response(self, status: StatusCode) -> Response {
+        let mut res = Response::new(status);
+        res.set_content_type("text/plain;charset=utf-8");
+        res.set_body(self.to_string());
+        res.insert_ext(self);
+        res
+    }
+}
+
+impl std::fmt::Display for ErrorPayload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}", self.plugin, self.message)?;
+        if let Some(loc) = &self.loc {
+            write!(f, "\n  {}", loc.file)?;
+            if let (Some(line), Some(column)) = (loc.line, loc.column) {
+                write!(f, ":{}:{}", line, column)?;
+            }
+        }
+        if let Some(frame) = &self.frame {
+            write!(f, "\n{}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HmrUpdate {
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// 接受更新的模块（HMR 边界）
+    pub path: String,
+    /// 真正需要重新请求的模块
+    pub accepted_path: String,
+    pub timestamp: u128,
+}
+
+/// 浏览器发给服务端的消息
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum HmrClientMessage {
+    Invalidate {
+        path: String,
+        message: Option<String>,
+    },
+}
+
+/// 管理所有连上来的浏览器，负责广播更新
+#[derive(Clone)]
+pub struct HmrServer {
+    config: Arc<Config>,
+    module_graph: Arc<RwLock<ModuleGraph>>,
+    plugins: Arc<PluginContainer>,
+    clients: Arc<RwLock<HashMap<usize, WebSocketConnection>>>,
+    next_id: Arc<AtomicUsize>,
+    /// 最近一次的错误，浏览器在出错之后才连上来的话也要能看到
+    last_error: Arc<RwLock<Option<ErrorPayload>>>,
+}
+
+impl HmrServer {
+    pub fn new(
+        config: Arc<Config>,
+        module_graph: Arc<RwLock<ModuleGraph>>,
+        plugins: Arc<PluginContainer>,
+    ) -> Self {
+        HmrServer {
+            config,
+            module_graph,
+            plugins,
+            clients: Arc::new(RwLock::new(HashMap::new())),
+            next_id: Arc::new(AtomicUsize::new(0)),
+            last_error: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    /// 处理一个 WebSocket 连接，直到浏览器断开
+    pub async fn handle_connection(&self, mut stream: WebSocketConnection) -> tide::Result<()> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        stream.send_json(&HmrPayload::Connected).await?;
+        if let Some(err) = self.last_error.read().await.clone() {
+            stream.send_json(&HmrPayload::Error { err }).await?;
+        }
+        self.clients.write().await.insert(id, stream.clone());
+
+        while let Some(Ok(message)) = stream.next().await {
+            if let Message::Text(text) = message {
+                match serde_json::from_str::<HmrClientMessage>(&text) {
+                    Ok(HmrClientMessage::Invalidate { path, message }) => {
+                        println!(
+                            "[hmr] invalidate {}{}",
+                            path,
+                            message.map(|m| format!(": {}", m)).unwrap_or_default()
+                        );
+                        self.on_invalidate(path).await;
+                    }
+                    Err(e) => println!("[hmr] unknown message: {} ({})", text, e),
+                }
+            }
+        }
+
+        self.clients.write().await.remove(&id);
+        Ok(())
+    }
+
+    pub async fn send(&self, payload: &HmrPayload) {
+        let clients: Vec<(usize, WebSocketConnection)> = self
+            .clients
+            .read()
+            .await
+            .iter()
+            .map(|(id, conn)| (*id, conn.clone()))
+            .collect();
+
+        for (id, conn) in clients {
+            if conn.send_json(payload).await.is_err() {
+                // 发送失败说明浏览器已经断开了
+                self.clients.write().await.remove(&id);
+            }
+        }
+    }
+
+    /// 把错误显示到浏览器的错误浮层上，下一次更新或者刷新页面的时候浮层会消失
+    pub async fn send_error(&self, err: ErrorPayload) {
+        println!("{}", err);
+        *self.last_error.write().await = Some(err.clone());
+        self.send(&HmrPayload::Error { err }).await;
+    }
+
+    /// 文件变化之后，先交给插件的 handle_hot_update，
+    /// 再沿着模块图找到 HMR 边界，找不到边界就只能整页刷新
+    pub async fn on_file_change(&self, event: &WatchEvent) {
+        let file = event.path.as_path();
+        // 文件改过之后之前的错误可能已经修好了，还有错的话重新请求的时候会再报
+        *self.last_error.write().await = None;
+        let graph = self.module_graph.read().await;
+        let mut modules = graph.get_modules_by_file(file);
+        // 新建、删除的文件可能改变了 import.meta.glob 的匹配结果
+        if event.kind != WatchEventKind::Change {
+            for url in graph.get_modules_by_glob_dir(file) {
+                if !modules.contains(&url) {
+                    modules.push(url);
+                }
+            }
+        }
+        drop(graph);
+        let urls = match self.plugins.handle_hot_update(event, modules).await {
+            HotUpdate::Modules(urls) => urls,
+            HotUpdate::FullReload => {
+                let path = file_to_url(&self.config, file);
+                self.send(&HmrPayload::FullReload { path }).await;
+                return;
+            }
+            HotUpdate::Handled => return,
+        };
+        let timestamp = now();
+        let mut graph = self.module_graph.write().await;
+
+        if urls.is_empty() {
+            // 不在模块图里的文件（还没被请求过的模块、图片之类的静态资源）不用管，
+            // 除了 html 和 <link rel="stylesheet"> 引用的 css，它们不会经过模块图
+            drop(graph);
+            let path = file_to_url(&self.config, file);
+            match file.extension().and_then(|ext| ext.to_str()) {
+                Some("html") => self.send(&HmrPayload::FullReload { path }).await,
+                Some("css") => {
+                    if let Some(path) = path {
+                        println!("[hmr] css update {}", path);
+                        let updates = vec![HmrUpdate {
+                            kind: "css-update".to_string(),
+                            path: path.clone(),
+                            accepted_path: path,
+                            timestamp,
+                        }];
+                        self.send(&HmrPayload::Update { updates }).await;
+                    }
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        for url in &urls {
+            graph.invalidate_module(url, timestamp);
+        }
+        let mut boundaries = Some(Vec::new());
+        for url in &urls {
+            boundaries = boundaries
+                .zip(graph.propagate_update(url))
+                .map(|(mut all, found)| {
+                    all.extend(found);
+                    all
+                });
+        }
+        drop(graph);
+
+        println!("[hmr] file changed: {}", urls.join(", "));
+        self.send(&to_payload(boundaries, urls.first().cloned(), timestamp))
+            .await;
+    }
+
+    /// 浏览器里的模块调用了 import.meta.hot.invalidate()
+    async fn on_invalidate(&self, url: String) {
+        let timestamp = now();
+        let mut graph = self.module_graph.write().await;
+        graph.invalidate_module(&url, timestamp);
+        let boundaries = graph.propagate_invalidate(&url);
+        drop(graph);
+        self.send(&to_payload(boundaries, Some(url), timestamp))
+            .await;
+    }
+}
+
+fn to_payload(
+    boundaries: Option<Vec<HmrBoundary>>,
+    path: Option<String>,
+    timestamp: u128,
+) -> HmrPayload {
+    match boundaries {
+        Some(boundaries) => {
+            let updates: Vec<HmrUpdate> = boundaries
+                .into_iter()
+                .map(|b| HmrUpdate {
+                    kind: "js-update".to_string(),
+                    path: b.boundary,
+                    accepted_path: b.accepted_via,
+                    timestamp,
+                })
+                .collect();
+            for update in &updates {
+                println!(
+                    "[hmr] update {} (via {})",
+                    update.path, update.accepted_path
+                );
+            }
+            HmrPayload::Update { updates }
+        }
+        None => {
+            println!("[hmr] no hmr boundary, page reload");
+            HmrPayload::FullReload { path }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl WatchListener for HmrServer {
+    async fn on_change(&self, event: &WatchEvent) {
+        // 文件夹本身的变化不需要通知浏览器，node_modules 里包的增删除外
+        if event.path.is_dir() && !event.is_dependency_change(&self.config) {
+            return;
+        }
+        self.on_file_change(event).await;
+    }
+}
+
+/// 项目里装了 react-refresh 才开启 React 组件的状态保持
+pub fn has_react_refresh(root_dir: &Path) -> bool {
+    root_dir
+        .join("node_modules/react-refresh/package.json")
+        .exists()
+}
+
+/// 给用到 import.meta.hot 的模块注入 hot context，加在模块最前面
+pub fn inject_hot_context(edits: &mut CodeEdits, url: &str, config: &Config) {
+    edits.insert(
+        0,
+        format!(
+            "import {{ createHotContext as __arashi__createHotContext }} from \"{}\";\nimport.meta.hot = __arashi__createHotContext(\"{}\");\n",
+            config.server_url(CLIENT_PATH),
+            url
+        ),
+    );
+}
+
+/// 返回给浏览器的客户端运行时，WebSocket 和 ping 的路径带上 base
+pub fn client_script(config: &Config) -> String {
+    CLIENT_SCRIPT.replace(
+        "__ARASHI_BASE__",
+        &serde_json::to_string(config.base.trim_end_matches('/')).unwrap_or_default(),
+    )
+}
+
+/// 在 </head> 前面注入客户端脚本，开启 react-refresh 的话还要先把 runtime 挂到全局
+pub fn inject_client_script(html: &str, react_refresh: bool, config: &Config) -> String {
+    let mut scripts = format!(
+        "<script type=\"module\" src=\"{}\"></script>\n",
+        config.server_url(CLIENT_PATH)
+    );
+    if react_refresh {
+        scripts.push_str(&format!(
+            r#"<script type="module">
+import RefreshRuntime from "{}";
+RefreshRuntime.injectIntoGlobalHook(window);
+window.$RefreshReg$ = () => {{}};
+window.$RefreshSig$ = () => (type) => type;
+</script>
+"#,
+            config.server_url(REACT_REFRESH_PATH)
+        ));
+    }
+
+    match html.find("</head>") {
+        Some(index) => format!("{}{}{}", &html[..index], scripts, &html[index..]),
+        None => format!("{}{}", scripts, html),
+    }
+}
+
+/// 用 react-refresh 包裹组件模块：执行前替换全局的 $RefreshReg$，执行后接受自身更新
+pub fn wrap_react_refresh(edits: &mut CodeEdits, code: &str, url: &str, config: &Config) {
+    // 没有注册组件的模块（比如 main.tsx）不能自己接受更新
+    if !code.contains("$RefreshReg$(") {
+        return;
+    }
+
+    let runtime = config.server_url(REACT_REFRESH_PATH);
+    edits.insert(
+        0,
+        format!(
+            r#"import RefreshRuntime from "{runtime}";
+let prevRefreshReg;
+let prevRefreshSig;
+if (import.meta.hot) {{
+  prevRefreshReg = window.$RefreshReg$;
+  prevRefreshSig = window.$RefreshSig$;
+  window.$RefreshReg$ = (type, id) => {{
+    RefreshRuntime.register(type, "{url} " + id);
+  }};
+  window.$RefreshSig$ = RefreshRuntime.createSignatureFunctionForTransform;
+}}
+"#
+        ),
+    );
+    edits.insert(
+        code.len(),
+        r#"
+if (import.meta.hot) {
+  window.$RefreshReg$ = prevRefreshReg;
+  window.$RefreshSig$ = prevRefreshSig;
+  import.meta.hot.accept();
+  clearTimeout(window.__arashi_refresh_timer__);
+  window.__arashi_refresh_timer__ = setTimeout(() => RefreshRuntime.performReactRefresh(), 30);
+}
+"#,
+    );
+}
+
+fn file_to_url(config: &Config, file: &Path) -> Option<String> {
+    // public 目录下的文件是直接挂在根路径上的
+    let relative = file
+        .strip_prefix(config.public_dir())
+        .or_else(|_| file.strip_prefix(&config.root))
+        .ok()?;
+    let relative = relative.to_string_lossy().replace('\\', "/");
+    Some(format!("/{}", relative))
+}
+
+fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
@@ -0,0 +1,152 @@
+use oxc_allocator::Allocator;
+use oxc_ast::ast::{
+    Argument, ArrayExpressionElement, CallExpression, Expression, ImportExpression, Statement,
+    StringLiteral,
+};
+use oxc_ast::visit::walk;
+use oxc_ast::Visit;
+use oxc_parser::{Parser, ParserReturn};
+use oxc_span::{SourceType, Span};
+
+/// 源码里的一个导入路径，start/end 是路径（不含引号）在源码中的位置
+#[derive(Debug, Clone)]
+pub struct ImportSpecifier {
+    pub specifier: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportAnalysis {
+    /// 所有静态导入、重新导出和路径是字符串常量的动态导入，按出现顺序排列
+    pub imports: Vec<ImportSpecifier>,
+    /// import.meta.hot.accept('./dep') 里声明接受更新的依赖
+    pub accepted_deps: Vec<String>,
+    /// 调用了 import.meta.hot.accept() 或 accept(cb)，自己就能接受自己的更新
+    pub self_accepting: bool,
+}
+
+/// 解析转换后的 js，找出模块依赖了谁、接受了哪些模块的热更新
+pub fn analyze_imports(code: &str) -> ImportAnalysis {
+    let allocator = Allocator::default();
+    let ParserReturn {
+        program,
+        errors,
+        panicked,
+        ..
+    } = Parser::new(&allocator, code, SourceType::mjs()).parse();
+
+    let mut analysis = ImportAnalysis::default();
+    if panicked || !errors.is_empty() {
+        println!("[import-analysis] parse error: {:?}", errors);
+        return analysis;
+    }
+
+    // import / export 语句只能出现在顶层
+    for stmt in &program.body {
+        let source = match stmt {
+            Statement::ImportDeclaration(decl) => Some(&decl.source),
+            Statement::ExportAllDeclaration(decl) => Some(&decl.source),
+            Statement::ExportNamedDeclaration(decl) => decl.source.as_ref(),
+            _ => None,
+        };
+        if let Some(source) = source {
+            analysis.imports.push(literal_specifier(source));
+        }
+    }
+
+    // import() 和 import.meta.hot.accept() 可能出现在任何地方，需要遍历整棵树
+    ImportVisitor {
+        analysis: &mut analysis,
+    }
+    .visit_program(&program);
+
+    analysis.imports.sort_by_key(|import| import.start);
+    analysis
+}
+
+/// 'react'、'react-dom/client'、'@scope/pkg' 这类第三方依赖
+pub fn is_bare_import(specifier: &str) -> bool {
+    !specifier.starts_with('.')
+        && !specifier.starts_with('/')
+        && !specifier.starts_with("http://")
+        && !specifier.starts_with("https://")
+        && !specifier.starts_with("data:")
+}
+
+fn literal_specifier(literal: &StringLiteral) -> ImportSpecifier {
+    to_specifier(literal.value.to_string(), literal.span)
+}
+
+fn to_specifier(specifier: String, span: Span) -> ImportSpecifier {
+    // span 包含了两边的引号（或者反引号）
+    ImportSpecifier {
+        specifier,
+        start: span.start as usize + 1,
+        end: span.end as usize - 1,
+    }
+}
+
+/// 找 import('...') 和 import.meta.hot.accept(...)
+struct ImportVisitor<'b> {
+    analysis: &'b mut ImportAnalysis,
+}
+
+impl<'a> Visit<'a> for ImportVisitor<'_> {
+    fn visit_import_expression(&mut self, expr: &ImportExpression<'a>) {
+        match &expr.source {
+            Expression::StringLiteral(source) => {
+                self.analysis.imports.push(literal_specifier(source));
+            }
+            // import(`./pages/home.tsx`) 这种没有插值的模板字符串也是确定的路径
+            Expression::TemplateLiteral(source) if source.expressions.is_empty() => {
+                if let Some(quasi) = source.quasis.first() {
+                    self.analysis
+                        .imports
+                        .push(to_specifier(quasi.value.raw.to_string(), source.span));
+                }
+            }
+            // 路径是变量的动态导入在服务端没法确定
+            _ => {}
+        }
+        walk::walk_import_expression(self, expr);
+    }
+
+    fn visit_call_expression(&mut self, expr: &CallExpression<'a>) {
+        if is_hot_accept(&expr.callee) {
+            match expr.arguments.first() {
+                // accept('./dep', cb)
+                Some(Argument::StringLiteral(dep)) => {
+                    self.analysis.accepted_deps.push(dep.value.to_string());
+                }
+                // accept(['./a', './b'], cb)
+                Some(Argument::ArrayExpression(deps)) => {
+                    for element in &deps.elements {
+                        if let ArrayExpressionElement::StringLiteral(dep) = element {
+                            self.analysis.accepted_deps.push(dep.value.to_string());
+                        }
+                    }
+                }
+                // accept() / accept(cb)
+                _ => self.analysis.self_accepting = true,
+            }
+        }
+        walk::walk_call_expression(self, expr);
+    }
+}
+
+fn is_hot_accept(callee: &Expression) -> bool {
+    let Expression::StaticMemberExpression(accept) = callee else {
+        return false;
+    };
+    let Expression::StaticMemberExpression(hot) = &accept.object else {
+        return false;
+    };
+    let Expression::MetaProperty(meta) = &hot.object else {
+        return false;
+    };
+    accept.property.name == "accept"
+        && hot.property.name == "hot"
+        && meta.meta.name == "import"
+        && meta.property.name == "meta"
+}
@@ -0,0 +1,61 @@
+use lightningcss::stylesheet::{MinifyOptions, ParserOptions, PrinterOptions, StyleSheet};
+use oxc_allocator::Allocator;
+use oxc_codegen::{CodeGenerator, CodegenOptions};
+use oxc_minifier::{CompressOptions, MangleOptions, Minifier, MinifierOptions};
+use oxc_parser::{Parser, ParserReturn};
+use oxc_span::SourceType;
+
+/// 压缩一个 esm 文件：去掉没用的代码（compress）、缩短局部变量名（mangle）、去掉空白。
+/// 顶层的名字不改，文件之间的导入导出还是原来的名字
+pub fn minify(source: &str) -> Result<String, String> {
+    let allocator = Allocator::default();
+    let ParserReturn {
+        mut program,
+        errors,
+        panicked,
+        ..
+    } = Parser::new(&allocator, source, SourceType::mjs()).parse();
+    if panicked || !errors.is_empty() {
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        return Err(messages.join("; "));
+    }
+
+    let options = MinifierOptions {
+        mangle: Some(MangleOptions {
+            top_level: false,
+            debug: false,
+        }),
+        compress: Some(CompressOptions::default()),
+    };
+    let ret = Minifier::new(options).build(&allocator, &mut program);
+    Ok(CodeGenerator::new()
+        .with_options(CodegenOptions {
+            minify: true,
+            comments: false,
+            ..CodegenOptions::default()
+        })
+        .with_symbol_table(ret.symbol_table)
+        .build(&program)
+        .code)
+}
+
+/// 压缩 css：合并能合并的规则、缩短颜色这类值、去掉空白
+pub fn minify_css(source: &str) -> Result<String, String> {
+    let mut sheet =
+        StyleSheet::parse(source, ParserOptions::default()).map_err(|e| e.to_string())?;
+    sheet
+        .minify(MinifyOptions::default())
+        .map_err(|e| e.to_string())?;
+    sheet
+        .to_css(PrinterOptions {
+            minify: true,
+            ..PrinterOptions::default()
+        })
+        .map(|result| result.code)
+        .map_err(|e| e.to_string())
+}
+
+/// 1234 => "1.23 kB"，和 vite 的输出一样按 1000 算
+pub fn format_size(size: usize) -> String {
+    format!("{:.2} kB", size as f64 / 1000.0)
+}
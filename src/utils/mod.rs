@@ -1,3 +1,19 @@
+pub mod build;
+pub mod bundler;
+pub mod config;
+pub mod css;
+pub mod error;
 pub mod fs;
+pub mod glob;
+pub mod hmr;
+pub mod import_analysis;
+pub mod minify;
+pub mod module_graph;
+pub mod package_manager;
 pub mod prebuild;
+pub mod resolver;
+pub mod scanner;
+pub mod sourcemap;
 pub mod transform;
+pub mod transform_cache;
+pub mod watcher;
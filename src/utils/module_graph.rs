@@ -0,0 +1,287 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// 模块图里的一个节点，用 url 标识（比如 /src/App.tsx、/@modules/react）
+#[derive(Debug, Clone)]
+pub struct ModuleNode {
+    pub url: String,
+    /// 对应的磁盘文件，/@modules/ 这类虚拟模块没有
+    pub file: Option<PathBuf>,
+    /// 谁导入了我
+    pub importers: HashSet<String>,
+    /// 我导入了谁
+    pub imported_modules: HashSet<String>,
+    /// 通过 import.meta.hot.accept('./dep') 接受了哪些依赖的更新
+    pub accepted_hmr_deps: HashSet<String>,
+    /// import.meta.glob 匹配的目录，里面新建、删除文件的时候这个模块要重新转换
+    pub glob_dirs: Vec<PathBuf>,
+    pub is_self_accepting: bool,
+    /// 最终返回给浏览器的代码，文件变化之后清空
+    pub transform_result: Option<String>,
+    /// transform_result 对应的 source map（JSON），转换时没有生成的话是 None
+    pub transform_map: Option<String>,
+    /// 最近一次热更新的时间，导入它的模块要带上 ?t= 绕过浏览器缓存
+    pub last_hmr_timestamp: u128,
+}
+
+impl ModuleNode {
+    fn new(url: String, file: Option<PathBuf>) -> Self {
+        ModuleNode {
+            url,
+            file,
+            importers: HashSet::new(),
+            imported_modules: HashSet::new(),
+            accepted_hmr_deps: HashSet::new(),
+            glob_dirs: Vec::new(),
+            is_self_accepting: false,
+            transform_result: None,
+            transform_map: None,
+            last_hmr_timestamp: 0,
+        }
+    }
+}
+
+/// 一次热更新的边界：boundary 接受了 accepted_via 的更新
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HmrBoundary {
+    pub boundary: String,
+    pub accepted_via: String,
+}
+
+/// 服务端的模块依赖图，在模块被请求、转换的时候逐步建立起来
+#[derive(Debug, Default)]
+pub struct ModuleGraph {
+    url_to_module: HashMap<String, ModuleNode>,
+    file_to_modules: HashMap<PathBuf, HashSet<String>>,
+}
+
+impl ModuleGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_module(&self, url: &str) -> Option<&ModuleNode> {
+        self.url_to_module.get(url)
+    }
+
+    pub fn get_modules_by_file(&self, file: &Path) -> Vec<String> {
+        self.file_to_modules
+            .get(file)
+            .map(|urls| urls.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// glob 匹配的目录包含这个文件的模块
+    pub fn get_modules_by_glob_dir(&self, file: &Path) -> Vec<String> {
+        self.url_to_module
+            .values()
+            .filter(|node| node.glob_dirs.iter().any(|dir| file.starts_with(dir)))
+            .map(|node| node.url.clone())
+            .collect()
+    }
+
+    pub fn set_glob_dirs(&mut self, url: &str, dirs: Vec<PathBuf>) {
+        if let Some(node) = self.url_to_module.get_mut(url) {
+            node.glob_dirs = dirs;
+        }
+    }
+
+    pub fn ensure_entry(&mut self, url: &str, file: Option<PathBuf>) -> &mut ModuleNode {
+        if let Some(file) = &file {
+            self.file_to_modules
+                .entry(file.clone())
+                .or_default()
+                .insert(url.to_string());
+        }
+        let node = self
+            .url_to_module
+            .entry(url.to_string())
+            .or_insert_with(|| ModuleNode::new(url.to_string(), None));
+        // 先作为别人的依赖被记录下来的节点，这时候才知道对应的文件
+        if node.file.is_none() {
+            node.file = file;
+        }
+        node
+    }
+
+    /// 模块转换完之后，更新它的依赖关系和转换结果
+    pub fn update_module_info(
+        &mut self,
+        url: &str,
+        imported: HashSet<String>,
+        accepted_hmr_deps: HashSet<String>,
+        is_self_accepting: bool,
+        transform_result: String,
+        transform_map: Option<String>,
+    ) {
+        let prev_imports = match self.url_to_module.get_mut(url) {
+            Some(node) => {
+                let prev = std::mem::replace(&mut node.imported_modules, imported.clone());
+                node.accepted_hmr_deps = accepted_hmr_deps;
+                node.is_self_accepting = is_self_accepting;
+                node.transform_result = Some(transform_result);
+                node.transform_map = transform_map;
+                prev
+            }
+            None => return,
+        };
+
+        // 不再导入的模块，要把自己从它们的 importers 里去掉
+        for dep in prev_imports.difference(&imported) {
+            if let Some(dep_node) = self.url_to_module.get_mut(dep) {
+                dep_node.importers.remove(url);
+            }
+        }
+        for dep in &imported {
+            self.url_to_module
+                .entry(dep.clone())
+                .or_insert_with(|| ModuleNode::new(dep.clone(), None))
+                .importers
+                .insert(url.to_string());
+        }
+    }
+
+    /// 清掉模块的转换结果。没有接受它更新的 importer 也要一起清掉，
+    /// 因为它们的导入路径上要带上新的 ?t= 时间戳
+    pub fn invalidate_module(&mut self, url: &str, timestamp: u128) {
+        let mut seen = HashSet::new();
+        self.invalidate(url, timestamp, &mut seen);
+    }
+
+    fn invalidate(&mut self, url: &str, timestamp: u128, seen: &mut HashSet<String>) {
+        if !seen.insert(url.to_string()) {
+            return;
+        }
+        let importers = match self.url_to_module.get_mut(url) {
+            Some(node) => {
+                node.transform_result = None;
+                node.transform_map = None;
+                node.last_hmr_timestamp = timestamp;
+                node.importers.clone()
+            }
+            None => return,
+        };
+        for importer in importers {
+            let accepts = self
+                .url_to_module
+                .get(&importer)
+                .map(|node| node.accepted_hmr_deps.contains(url))
+                .unwrap_or(false);
+            if !accepts {
+                self.invalidate(&importer, timestamp, seen);
+            }
+        }
+    }
+
+    /// 从变化的模块开始沿着 importers 往上找，直到找到能接受更新的边界。
+    /// 某条链路一直走到入口都没人接受，返回 None，只能整页刷新
+    pub fn propagate_update(&self, url: &str) -> Option<Vec<HmrBoundary>> {
+        let mut boundaries = HashSet::new();
+        let mut chain = vec![url.to_string()];
+        if self.propagate(url, &mut boundaries, &mut chain) {
+            return None;
+        }
+        Some(boundaries.into_iter().collect())
+    }
+
+    /// import.meta.hot.invalidate()：模块自己放弃了热更新，从它的 importers 开始找边界
+    pub fn propagate_invalidate(&self, url: &str) -> Option<Vec<HmrBoundary>> {
+        let node = self.url_to_module.get(url)?;
+        let mut boundaries = HashSet::new();
+        if node.importers.is_empty() {
+            return None;
+        }
+        for importer in &node.importers {
+            let mut chain = vec![url.to_string(), importer.clone()];
+            let importer_node = self.url_to_module.get(importer)?;
+            if importer_node.accepted_hmr_deps.contains(url) {
+                boundaries.insert(HmrBoundary {
+                    boundary: importer.clone(),
+                    accepted_via: url.to_string(),
+                });
+            } else if self.propagate(importer, &mut boundaries, &mut chain) {
+                return None;
+            }
+        }
+        Some(boundaries.into_iter().collect())
+    }
+
+    /// 返回 true 表示走到了死胡同（需要整页刷新）
+    fn propagate(
+        &self,
+        url: &str,
+        boundaries: &mut HashSet<HmrBoundary>,
+        chain: &mut Vec<String>,
+    ) -> bool {
+        let node = match self.url_to_module.get(url) {
+            Some(node) => node,
+            None => return true,
+        };
+
+        if node.is_self_accepting {
+            boundaries.insert(HmrBoundary {
+                boundary: node.url.clone(),
+                accepted_via: node.url.clone(),
+            });
+            return false;
+        }
+
+        // 没有人导入它，说明已经到入口了
+        if node.importers.is_empty() {
+            return true;
+        }
+
+        for importer in &node.importers {
+            let importer_node = match self.url_to_module.get(importer) {
+                Some(node) => node,
+                None => return true,
+            };
+            if importer_node.accepted_hmr_deps.contains(url) {
+                boundaries.insert(HmrBoundary {
+                    boundary: importer.clone(),
+                    accepted_via: url.to_string(),
+                });
+                continue;
+            }
+            // 循环依赖
+            if chain.contains(importer) {
+                return true;
+            }
+            chain.push(importer.clone());
+            let dead_end = self.propagate(importer, boundaries, chain);
+            chain.pop();
+            if dead_end {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// 把模块里的导入路径解析成服务端的 url，比如 /src/App.tsx 里的 ./components/Comp.tsx
+/// => /src/components/Comp.tsx，热更新加的 ?t= 不算在模块的标识里
+pub fn resolve_url(importer: &str, specifier: &str) -> String {
+    let base = url::Url::parse("http://arashi.local").unwrap();
+    match base
+        .join(importer)
+        .and_then(|importer| importer.join(specifier))
+    {
+        Ok(resolved) => module_url(&resolved),
+        Err(_) => specifier.to_string(),
+    }
+}
+
+/// 请求对应的模块 url：路径加上除了 t= 之外的查询参数，
+/// /src/logo.png?raw 和 /src/logo.png 是两个不同的模块
+pub fn module_url(url: &url::Url) -> String {
+    let query: Vec<&str> = url
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|param| !param.is_empty() && !param.starts_with("t="))
+        .collect();
+    match query.is_empty() {
+        true => url.path().to_string(),
+        false => format!("{}?{}", url.path(), query.join("&")),
+    }
+}
@@ -0,0 +1,258 @@
+use crate::utils::fs;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// 按优先级排列的 lock 文件，同一个目录里有多个的时候用前面的
+const LOCK_FILES: [(&str, PackageManager); 5] = [
+    ("pnpm-lock.yaml", PackageManager::Pnpm),
+    ("yarn.lock", PackageManager::Yarn),
+    ("package-lock.json", PackageManager::Npm),
+    ("bun.lock", PackageManager::Bun),
+    ("bun.lockb", PackageManager::Bun),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageManager {
+    Npm,
+    Pnpm,
+    /// yarn 1.x
+    Yarn,
+    /// yarn 2+，只支持 nodeLinker: node-modules
+    YarnBerry,
+    Bun,
+    /// 没有 lock 文件，只有 node_modules
+    Unknown,
+}
+
+impl fmt::Display for PackageManager {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            PackageManager::Npm => "npm",
+            PackageManager::Pnpm => "pnpm",
+            PackageManager::Yarn => "yarn",
+            PackageManager::YarnBerry => "yarn berry",
+            PackageManager::Bun => "bun",
+            PackageManager::Unknown => "未知（没有 lock 文件）",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// 项目用的包管理器，以及 lock 文件里锁定的版本
+///
+/// lock 文件不是必须的：依赖都是按 node_modules 解析的，
+/// lock 文件只用来在 pnpm 的 .pnpm 目录里有多个版本的时候挑出锁定的那个
+#[derive(Debug)]
+pub struct PackageManagerInfo {
+    pub kind: PackageManager,
+    /// lock 文件所在的目录，monorepo 里是 workspace 的根目录
+    pub workspace_root: PathBuf,
+    pub lock_file: Option<PathBuf>,
+    /// 包名 => lock 文件里的版本，用到的时候才解析 lock 文件
+    versions: OnceLock<HashMap<String, Vec<String>>>,
+}
+
+impl PackageManagerInfo {
+    /// 从项目目录开始往上找 lock 文件，monorepo 的 lock 文件一般在 workspace 的根目录
+    pub fn detect(root_dir: &Path) -> Self {
+        for dir in root_dir.ancestors() {
+            for (file, kind) in LOCK_FILES {
+                let lock_file = dir.join(file);
+                if !lock_file.is_file() {
+                    continue;
+                }
+                let kind = match kind {
+                    // berry 的 lock 文件是 yaml 格式，开头有 __metadata
+                    PackageManager::Yarn
+                        if fs::read_file_content(&lock_file)
+                            .map(|content| content.contains("__metadata:"))
+                            .unwrap_or(false) =>
+                    {
+                        PackageManager::YarnBerry
+                    }
+                    kind => kind,
+                };
+                if kind == PackageManager::YarnBerry && dir.join(".pnp.cjs").exists() {
+                    println!(
+                        "[deps] 不支持 yarn 的 Plug'n'Play，请在 .yarnrc.yml 里设置 nodeLinker: node-modules"
+                    );
+                }
+                return PackageManagerInfo {
+                    kind,
+                    workspace_root: dir.to_path_buf(),
+                    lock_file: Some(lock_file),
+                    versions: OnceLock::new(),
+                };
+            }
+        }
+        PackageManagerInfo {
+            kind: from_package_json(root_dir),
+            workspace_root: root_dir.to_path_buf(),
+            lock_file: None,
+            versions: OnceLock::new(),
+        }
+    }
+
+    /// lock 文件里锁定的版本，可能有多个（不同的包依赖了不同的版本）
+    pub fn locked_versions(&self, name: &str) -> &[String] {
+        let versions = self.versions.get_or_init(|| {
+            self.lock_file
+                .as_ref()
+                .and_then(|file| fs::read_file_content(file).ok())
+                .map(|content| parse_lock_file(self.kind, &content))
+                .unwrap_or_default()
+        });
+        versions.get(name).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
+
+/// 没有 lock 文件的时候看 package.json 的 packageManager 字段，比如 "pnpm@8.15.0"
+fn from_package_json(root_dir: &Path) -> PackageManager {
+    let package_manager = fs::read_file_content(root_dir.join("package.json"))
+        .ok()
+        .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+        .and_then(|json| {
+            json.get("packageManager")
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        });
+    match package_manager
+        .as_deref()
+        .and_then(|pm| pm.split('@').next())
+    {
+        Some("pnpm") => PackageManager::Pnpm,
+        Some("npm") => PackageManager::Npm,
+        Some("bun") => PackageManager::Bun,
+        Some("yarn") => match package_manager.as_deref() {
+            Some(pm) if pm.starts_with("yarn@1.") => PackageManager::Yarn,
+            _ => PackageManager::YarnBerry,
+        },
+        _ => PackageManager::Unknown,
+    }
+}
+
+fn parse_lock_file(kind: PackageManager, content: &str) -> HashMap<String, Vec<String>> {
+    let mut versions: HashMap<String, Vec<String>> = HashMap::new();
+    let entries = match kind {
+        PackageManager::Pnpm => parse_pnpm_lock(content),
+        PackageManager::Npm => parse_npm_lock(content),
+        PackageManager::Yarn | PackageManager::YarnBerry => parse_yarn_lock(content),
+        PackageManager::Bun => parse_bun_lock(content),
+        PackageManager::Unknown => Vec::new(),
+    };
+    for (name, version) in entries {
+        let list = versions.entry(name).or_default();
+        if !list.contains(&version) {
+            list.push(version);
+        }
+    }
+    versions
+}
+
+/// packages: 下面的 key，不同版本的 lock 文件写法不一样：
+/// `/react/18.2.0:`（v5）、`/react@18.2.0:`（v6）、`react@18.2.0:`（v9），
+/// 后面可能还跟着 peer 依赖，比如 `react-dom@18.2.0(react@18.2.0):`
+fn parse_pnpm_lock(content: &str) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    let mut in_packages = false;
+    for line in content.lines() {
+        if !line.starts_with(' ') {
+            in_packages = line.trim_end() == "packages:";
+            continue;
+        }
+        if !in_packages || line.starts_with("   ") || !line.trim_end().ends_with(':') {
+            continue;
+        }
+        let key = line
+            .trim()
+            .trim_end_matches(':')
+            .trim_matches(|c| c == '\'' || c == '"');
+        let key = key.trim_start_matches('/');
+        let key = key.split('(').next().unwrap_or(key);
+        // v5 是 name/version，版本号以数字开头
+        let v5 = key
+            .match_indices('/')
+            .find(|(i, _)| key[i + 1..].starts_with(|c: char| c.is_ascii_digit()))
+            .map(|(i, _)| (&key[..i], &key[i + 1..]));
+        let split = v5.or_else(|| {
+            let at = key.get(1..)?.find('@')? + 1;
+            Some((&key[..at], &key[at + 1..]))
+        });
+        if let Some((name, version)) = split {
+            // v5 的 peer 依赖跟在 _ 后面
+            let version = version.split('_').next().unwrap_or(version);
+            entries.push((name.to_string(), version.to_string()));
+        }
+    }
+    entries
+}
+
+/// lockfileVersion 2/3 的 packages 里是 "node_modules/react": { "version": "18.2.0" }
+fn parse_npm_lock(content: &str) -> Vec<(String, String)> {
+    let Ok(json) = serde_json::from_str::<serde_json::Value>(content) else {
+        return Vec::new();
+    };
+    let Some(packages) = json.get("packages").and_then(|p| p.as_object()) else {
+        return Vec::new();
+    };
+    packages
+        .iter()
+        .filter_map(|(path, info)| {
+            let name = path.rsplit("node_modules/").next()?;
+            let version = info.get("version")?.as_str()?;
+            (!name.is_empty()).then(|| (name.to_string(), version.to_string()))
+        })
+        .collect()
+}
+
+/// yarn 1：`react@^18.2.0, react@^18.0.0:` 下面跟着 `  version "18.2.0"`
+/// yarn berry：`"react@npm:^18.2.0":` 下面跟着 `  version: 18.2.0`
+fn parse_yarn_lock(content: &str) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    let mut current: Option<String> = None;
+    for line in content.lines() {
+        if !line.starts_with(' ') && line.trim_end().ends_with(':') {
+            let first = line.split(',').next().unwrap_or(line);
+            let first = first.trim().trim_end_matches(':').trim_matches('"');
+            current = first
+                .get(1..)
+                .and_then(|rest| rest.find('@'))
+                .map(|at| first[..at + 1].to_string());
+            continue;
+        }
+        let trimmed = line.trim();
+        if let (Some(name), Some(version)) = (&current, trimmed.strip_prefix("version")) {
+            let version = version.trim_start_matches(':').trim().trim_matches('"');
+            entries.push((name.clone(), version.to_string()));
+            current = None;
+        }
+    }
+    entries
+}
+
+/// bun.lock 是 jsonc：packages 里是 "react": ["react@18.2.0", ...]，bun.lockb 是二进制的，读不了
+fn parse_bun_lock(content: &str) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    let mut in_packages = false;
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("\"packages\"") {
+            in_packages = true;
+            continue;
+        }
+        if !in_packages {
+            continue;
+        }
+        // "react": ["react@18.2.0", "", {}, "sha512-..."],
+        let Some((_, value)) = trimmed.split_once(": [\"") else {
+            continue;
+        };
+        let spec = value.split('"').next().unwrap_or("");
+        if let Some(at) = spec.get(1..).and_then(|rest| rest.find('@')) {
+            entries.push((spec[..at + 1].to_string(), spec[at + 2..].to_string()));
+        }
+    }
+    entries
+}
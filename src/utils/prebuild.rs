@@ -1,126 +1,322 @@
+use crate::utils::bundler::{self, BundleOutput, BUNDLER_VERSION};
+use crate::utils::config::Config;
+use crate::utils::error::Error;
+use crate::utils::minify::{format_size, minify};
+use crate::utils::package_manager::PackageManagerInfo;
+use crate::utils::resolver::{split_package, ResolveKind, Resolver};
 use async_std::path::{Path, PathBuf};
-// use esbuild::*;
-use oxc_allocator::Allocator;
-use oxc_parser::{Parser, ParserReturn};
-use oxc_span::SourceType;
-use std::collections::{HashMap, HashSet};
-use std::process::Command;
-use tide::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+const METADATA_FILE: &str = "_metadata.json";
+
+/// 写到缓存目录（默认 node_modules/.arashi）的 _metadata.json 里，重启的时候用来判断缓存还能不能用
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DepMetadata {
+    hash: String,
+    optimized: HashMap<String, String>,
+}
 
 #[derive(Debug)]
 pub struct DepCache {
+    config: Arc<Config>,
     cache_dir: PathBuf,
     metadata: HashMap<String, String>, // 包名 -> 预构建文件路径
     building: HashSet<String>,         // 正在构建的包
+    stale: bool,                       // node_modules 变化之后，上次的产物不能再复用
 }
 
-/// TODO:想要做成预构建，但是现在是请求的时候按需构建。。。
 impl DepCache {
-    pub async fn new(root_dir: &Path) -> Self {
-        let cache_dir = root_dir.join("node_modules/.arashi");
-        async_std::fs::create_dir_all(&cache_dir).await.unwrap();
-
+    pub async fn new(config: Arc<Config>) -> Result<Self, Error> {
+        let cache_dir: PathBuf = config.cache_dir().into();
         let deps_dir = cache_dir.join("deps");
-        async_std::fs::create_dir_all(&deps_dir).await.unwrap();
+        async_std::fs::create_dir_all(&deps_dir)
+            .await
+            .map_err(|e| Error::io(&deps_dir, e))?;
 
-        Self {
+        Ok(Self {
+            config,
             cache_dir,
             metadata: HashMap::new(),
             building: HashSet::new(),
-        }
+            stale: false,
+        })
     }
 
-    pub async fn get_or_build(&mut self, pkg_name: &str, pkg_path: &Path) -> Result<PathBuf> {
-        // 如果已经有缓存，直接返回
-        if let Some(path) = self.get_cached_path(pkg_name) {
-            return Ok(path);
-        }
+    /// 启动时把扫描出来的依赖一次性预构建好。
+    ///
+    /// 一起构建可以让 react、react-dom 这些互相依赖的包共享 chunk，浏览器里只会有一份 React；
+    /// lock 文件、安装的版本和构建参数都没变、依赖也都构建过的话，直接复用上次的结果。
+    /// 返回是不是真的重新构建了
+    pub async fn prebundle(&mut self, deps: BTreeSet<String>) -> Result<bool, Error> {
+        let hash = self.deps_hash(&deps).await;
+        let metadata_path = self.cache_dir.join(METADATA_FILE);
+        // 被标记过期的话，hash 没变也要重新构建（npm link、patch-package、手改 node_modules 都不会改 lock 文件）
+        let stale = std::mem::take(&mut self.stale);
 
-        // 如果正在构建，等待构建完成
-        if self.building.contains(pkg_name) {
-            while self.building.contains(pkg_name) {
-                async_std::task::sleep(std::time::Duration::from_millis(50)).await;
+        let cached = match stale {
+            true => None,
+            false => async_std::fs::read_to_string(&metadata_path).await.ok(),
+        };
+        if let Some(content) = cached {
+            if let Ok(cached) = serde_json::from_str::<DepMetadata>(&content) {
+                let mut all_built = cached.hash == hash;
+                for dep in &deps {
+                    all_built = all_built
+                        && match cached.optimized.get(dep) {
+                            Some(file) => async_std::fs::metadata(self.cache_dir.join(file))
+                                .await
+                                .is_ok(),
+                            None => false,
+                        };
+                }
+                if all_built {
+                    println!("[deps] 依赖没有变化，复用预构建缓存");
+                    self.metadata = cached.optimized;
+                    return Ok(false);
+                }
             }
-            return Ok(self.get_cached_path(pkg_name).unwrap());
         }
 
-        // 开始构建
-        self.building.insert(pkg_name.to_string());
-        self.build_dep(pkg_name, pkg_path).await;
-        self.building.remove(pkg_name);
+        if deps.is_empty() {
+            return Ok(false);
+        }
 
-        Ok(self.get_cached_path(pkg_name).unwrap())
-    }
+        let resolver = Resolver::from_config(&self.config);
+        let package_manager = PackageManagerInfo::detect(&self.config.root);
+        println!(
+            "[deps] 包管理器: {}{}",
+            package_manager.kind,
+            package_manager
+                .lock_file
+                .map(|file| format!(" ({})", file.display()))
+                .unwrap_or_default()
+        );
+        let mut entries = Vec::new();
+        for dep in deps {
+            match resolver.resolve(&dep, &self.config.root, ResolveKind::Import) {
+                Some(path) => entries.push((dep, path)),
+                None => println!("[deps] 找不到依赖: {}", dep),
+            }
+        }
+        println!(
+            "[deps] 预构建依赖: {}",
+            entries
+                .iter()
+                .map(|(dep, _)| dep.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
 
-    pub async fn build_dep(&mut self, pkg_name: &str, pkg_path: &Path) {
+        // 旧的产物（尤其是共享 chunk）全部删掉重新生成
         let outdir = self.cache_dir.join("deps");
-        async_std::fs::create_dir_all(&outdir).await.unwrap();
-
-        let pkg_path = pkg_path.to_owned();
-        let outdir = outdir.to_owned();
-        let pkg_name_clone = pkg_name.to_owned(); // 克隆用于 spawn_blocking
-
-        async_std::task::spawn_blocking(move || {
-            Command::new("npx")
-                .arg("esbuild")
-                .arg(&pkg_path)
-                .arg("--bundle")
-                .arg("--format=esm")
-                .arg("--platform=browser")
-                .arg("--target=es2020")
-                .arg(format!(
-                    "--outfile={}/{}.js",
-                    outdir.display(),
-                    pkg_name_clone
-                ))
-                .status()
-        })
+        let _ = async_std::fs::remove_dir_all(&outdir).await;
+        async_std::fs::create_dir_all(&outdir)
+            .await
+            .map_err(|e| Error::io(&outdir, e))?;
+
+        let config = self.config.clone();
+        let bundle_entries = entries.clone();
+        let outputs =
+            async_std::task::spawn_blocking(move || bundle_deps(&config, &bundle_entries)).await?;
+        write_outputs(&outdir, outputs).await?;
+
+        self.metadata = entries
+            .into_iter()
+            .map(|(dep, _)| {
+                let file = format!("deps/{}.js", dep);
+                (dep, file)
+            })
+            .collect();
+        let metadata = DepMetadata {
+            hash,
+            optimized: self.metadata.clone(),
+        };
+        async_std::fs::write(
+            &metadata_path,
+            serde_json::to_string_pretty(&metadata).unwrap_or_default(),
+        )
         .await
-        .unwrap();
+        .map_err(|e| Error::io(&metadata_path, e))?;
+        Ok(true)
+    }
 
-        // // 读取源码并解析
-        // let source = async_std::fs::read_to_string(pkg_path).await.unwrap();
-        // let allocator = Allocator::default();
-        // let source_type = SourceType::from_path(pkg_path).unwrap();
+    /// package.json、lock 文件或者 node_modules 变化了，下次 prebundle 一定重新构建
+    pub fn mark_stale(&mut self) {
+        self.stale = true;
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
 
-        // let ParserReturn {
-        //     mut program,
-        //     errors,
-        //     panicked,
-        //     ..
-        // } = Parser::new(&allocator, &source, source_type).parse();
+    /// package.json + lock 文件 + 实际安装的版本 + 打包器版本 + 相关配置的 hash。
+    /// lock 文件可能在上层目录（monorepo），没有 lock 文件的时候只看 package.json；
+    /// 不改 lock 文件重新安装、npm link 之后，安装的版本和包的真实路径会变
+    async fn deps_hash(&self, deps: &BTreeSet<String>) -> String {
+        let mut hasher = Sha256::new();
+        let package_manager = PackageManagerInfo::detect(&self.config.root);
+        let package_json = self.config.root.join("package.json");
+        for file in std::iter::once(package_json).chain(package_manager.lock_file) {
+            if let Ok(content) = async_std::fs::read(&file).await {
+                hasher.update(file.to_string_lossy().as_bytes());
+                hasher.update(content);
+            }
+        }
+        for name in deps
+            .iter()
+            .filter_map(|dep| split_package(dep).map(|(name, _)| name))
+        {
+            let package_dir = self.config.root.join("node_modules").join(name);
+            if let Ok(real_dir) = std::fs::canonicalize(&package_dir) {
+                hasher.update(real_dir.to_string_lossy().as_bytes());
+            }
+            let package_json = package_dir.join("package.json");
+            if let Some(version) = std::fs::read_to_string(&package_json)
+                .ok()
+                .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+                .and_then(|json| {
+                    json.get("version")
+                        .and_then(|v| v.as_str())
+                        .map(String::from)
+                })
+            {
+                hasher.update(format!("{}@{}", name, version).as_bytes());
+            }
+        }
+        hasher.update(BUNDLER_VERSION.as_bytes());
+        hasher.update(self.config.deps_hash_input().as_bytes());
+        format!("{:x}", hasher.finalize())[..8].to_string()
+    }
 
-        // if panicked || !errors.is_empty() {
-        //     panic!("Parse Error: {:?}", errors);
-        // }
+    /// 预构建好的依赖，以及它们之间共享的 chunk（比如 /@modules/chunk-XXXX.js）
+    pub async fn get_prebuilt_path(&self, name: &str) -> Option<PathBuf> {
+        if let Some(path) = self.get_cached_path(name) {
+            return Some(path);
+        }
+        let chunk = self.cache_dir.join("deps").join(name);
+        if name.ends_with(".js") && async_std::fs::metadata(&chunk).await.is_ok() {
+            return Some(chunk);
+        }
+        None
+    }
 
-        // // 生成代码
-        // let result = CodeGenerator::new().build(&program);
+    /// 启动时没扫描到的依赖（比如只在运行时才拼出来的路径），请求的时候再单独构建。
+    ///
+    /// 打包的时候不拿着锁，其他依赖的请求和 HMR 不用等它；同一个依赖的请求等第一个构建完
+    pub async fn get_or_build(
+        cache: &RwLock<Self>,
+        pkg_name: &str,
+        pkg_path: &std::path::Path,
+    ) -> Result<PathBuf, Error> {
+        let (config, outdir) = {
+            let mut guard = cache.write().await;
+            // 如果已经有缓存，直接返回
+            if let Some(path) = guard.get_cached_path(pkg_name) {
+                return Ok(path);
+            }
+            // 开始构建，只记下 building，打包之前就把锁放掉
+            if guard.building.insert(pkg_name.to_string()) {
+                (guard.config.clone(), guard.cache_dir.join("deps"))
+            } else {
+                // 如果正在构建，等待构建完成
+                drop(guard);
+                loop {
+                    async_std::task::sleep(std::time::Duration::from_millis(50)).await;
+                    let guard = cache.read().await;
+                    if !guard.building.contains(pkg_name) {
+                        return guard
+                            .get_cached_path(pkg_name)
+                            .ok_or_else(|| Error::dep_build(pkg_name, "构建失败"));
+                    }
+                }
+            }
+        };
 
-        // // 写入文件
-        // let outfile = outdir.join(format!("{}.js", pkg_name));
-        // async_std::fs::write(&outfile, result.code).await.unwrap();
+        // optimizeDeps.exclude 里的依赖本来就是请求的时候才构建的
+        if !config
+            .optimize_deps
+            .exclude
+            .iter()
+            .any(|dep| dep == pkg_name)
+        {
+            println!(
+                "[deps] 发现新的依赖 {}，单独构建（它不会和预构建的依赖共享 chunk，建议重启）",
+                pkg_name
+            );
+        }
+        let result = build_dep(config, &outdir, pkg_name, pkg_path).await;
 
-        self.metadata
+        let mut cache = cache.write().await;
+        cache.building.remove(pkg_name);
+        result?;
+        cache
+            .metadata
             .insert(pkg_name.to_string(), format!("deps/{}.js", pkg_name));
+        cache
+            .get_cached_path(pkg_name)
+            .ok_or_else(|| Error::dep_build(pkg_name, "没有生成产物"))
     }
 
     pub fn get_cached_path(&self, pkg_name: &str) -> Option<PathBuf> {
         self.metadata.get(pkg_name).map(|p| self.cache_dir.join(p))
     }
+}
 
-    // async fn build_dependency(&self, pkg_name: &str, pkg_path: &Path) -> Result<PathBuf> {
-    //     let outdir = self.cache_dir.join("deps");
-    //     let outfile = outdir.join(format!("{}.js", pkg_name));
-
-    //     // 使用 OXC 进行转换和打包
-    //     let source = async_std::fs::read_to_string(pkg_path).await?;
-    //     let ret = Transformer::new(&allocator, "virtual.ts", &transform_options)
-    //         .build_with_symbols_and_scopes(symbols, scopes, &mut program);
+/// 单独构建一个依赖，写到 deps 目录
+async fn build_dep(
+    config: Arc<Config>,
+    outdir: &Path,
+    pkg_name: &str,
+    pkg_path: &std::path::Path,
+) -> Result<(), Error> {
+    let entries = vec![(pkg_name.to_string(), pkg_path.to_path_buf())];
+    let outputs = async_std::task::spawn_blocking(move || bundle_deps(&config, &entries)).await?;
+    write_outputs(outdir, outputs).await
+}
 
-    //     let result = transformer.transform(&source)?;
-    //     async_std::fs::write(&outfile, result.code).await?;
+/// 打包依赖，开了 optimizeDeps.minify 的话再压缩一遍
+fn bundle_deps(
+    config: &Config,
+    entries: &[(String, std::path::PathBuf)],
+) -> Result<Vec<BundleOutput>, Error> {
+    let outputs = bundler::bundle(config, entries)?;
+    if !config.optimize_deps.minify {
+        return Ok(outputs);
+    }
+    let mut minified = Vec::new();
+    for output in outputs {
+        let code = minify(&output.code)
+            .map_err(|e| Error::dep_build(&output.file_name, format!("压缩失败: {}", e)))?;
+        println!(
+            "[deps] 压缩 {}: {} -> {}",
+            output.file_name,
+            format_size(output.code.len()),
+            format_size(code.len())
+        );
+        minified.push(BundleOutput {
+            file_name: output.file_name,
+            code,
+        });
+    }
+    Ok(minified)
+}
 
-    //     Ok(outfile)
-    // }
+/// 把打包结果写到 deps 目录，react-dom/client 这种带子路径的依赖要先建好文件夹
+async fn write_outputs(outdir: &Path, outputs: Vec<BundleOutput>) -> Result<(), Error> {
+    for output in outputs {
+        let file = outdir.join(&output.file_name);
+        if let Some(parent) = file.parent() {
+            async_std::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| Error::io(parent, e))?;
+        }
+        async_std::fs::write(&file, output.code)
+            .await
+            .map_err(|e| Error::io(&file, e))?;
+    }
+    Ok(())
 }
@@ -0,0 +1,451 @@
+use crate::utils::config::{Config, ResolveConfig};
+use crate::utils::fs;
+use crate::utils::import_analysis::is_bare_import;
+use crate::utils::package_manager::PackageManagerInfo;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// package.json 里 exports / imports 能匹配的条件。
+/// 匹配的顺序是 package.json 里写的顺序，不是这里的顺序
+const DEFAULT_CONDITIONS: [&str; 5] = ["browser", "import", "module", "development", "default"];
+
+/// 导入路径没写后缀的时候依次尝试
+const EXTENSIONS: [&str; 7] = ["js", "mjs", "cjs", "jsx", "ts", "tsx", "json"];
+
+/// 没有 exports 的包，按这个顺序找入口
+const MAIN_FIELDS: [&str; 3] = ["browser", "module", "main"];
+
+/// browser 字段里映射成 false 的模块，浏览器里是个空模块
+pub const EMPTY_MODULE: &str = "arashi:empty";
+
+/// import 和 require 匹配的条件不一样：require 匹配 "require"，import 匹配 "import"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolveKind {
+    Import,
+    Require,
+}
+
+#[derive(Debug)]
+struct PackageJson {
+    dir: PathBuf,
+    json: Value,
+}
+
+impl PackageJson {
+    fn field(&self, name: &str) -> Option<&Value> {
+        self.json.get(name)
+    }
+}
+
+/// 和 Node 一致的模块解析：
+/// 相对路径补全后缀 / index，第三方依赖按 node_modules 往上找，
+/// 支持 package.json 的 exports、imports（#开头）、browser 字段，软链接会解析成真实路径
+#[derive(Debug)]
+pub struct Resolver {
+    root_dir: PathBuf,
+    conditions: Vec<String>,
+    /// 配置里的 resolve.alias
+    alias: ResolveConfig,
+    package_manager: PackageManagerInfo,
+    /// 目录 => 里面的 package.json，没有的也记下来，省得重复读文件
+    packages: Mutex<HashMap<PathBuf, Option<Arc<PackageJson>>>>,
+}
+
+impl Resolver {
+    pub fn new(root_dir: impl AsRef<Path>) -> Self {
+        let root_dir = root_dir.as_ref();
+        let root_dir = root_dir
+            .canonicalize()
+            .unwrap_or_else(|_| root_dir.to_path_buf());
+        Resolver {
+            conditions: DEFAULT_CONDITIONS.iter().map(|c| c.to_string()).collect(),
+            alias: ResolveConfig::default(),
+            package_manager: PackageManagerInfo::detect(&root_dir),
+            packages: Mutex::new(HashMap::new()),
+            root_dir,
+        }
+    }
+
+    /// 带上配置里的 resolve.alias 和额外的 resolve.conditions。
+    /// "development" 跟着 process.env.NODE_ENV 走，生产构建匹配 "production"，不能用包的开发版本
+    pub fn from_config(config: &Config) -> Self {
+        let mut resolver = Resolver::new(&config.root);
+        if config.node_env() == "production" {
+            for condition in resolver.conditions.iter_mut() {
+                if condition == "development" {
+                    *condition = "production".to_string();
+                }
+            }
+        }
+        let mut resolver = resolver.with_conditions(&config.resolve.conditions);
+        resolver.alias = config.resolve.clone();
+        resolver
+    }
+
+    /// 在默认条件之外再匹配这些条件，比如 "worker"、"production"
+    pub fn with_conditions(mut self, conditions: &[String]) -> Self {
+        for condition in conditions {
+            if !self.conditions.contains(condition) {
+                self.conditions.push(condition.clone());
+            }
+        }
+        self
+    }
+
+    /// 在 base_dir 里解析 specifier，找不到返回 None。
+    /// browser 字段里映射成 false 的模块会返回 EMPTY_MODULE
+    pub fn resolve(&self, specifier: &str, base_dir: &Path, kind: ResolveKind) -> Option<PathBuf> {
+        let aliased = self.alias.apply_alias(specifier);
+        let specifier = aliased.as_deref().unwrap_or(specifier);
+        let file = if specifier.starts_with('#') {
+            self.resolve_imports(specifier, base_dir, kind)?
+        } else if is_bare_import(specifier) {
+            // 所在的包可以通过 browser 字段把依赖换掉，比如 { "fs": false }
+            if let Some(mapped) = self.browser_mapping(specifier, base_dir, kind) {
+                return mapped;
+            }
+            self.resolve_bare(specifier, base_dir, kind)?
+        } else if let Some(absolute) = specifier.strip_prefix('/') {
+            // /src/main.ts 这种是相对项目根目录的
+            self.probe(&self.root_dir.join(absolute))?
+        } else {
+            self.probe(&base_dir.join(specifier))?
+        };
+        Some(self.browser_replacement(file, kind))
+    }
+
+    /// 'react-dom/client' => node_modules/react-dom 下面的 ./client
+    fn resolve_bare(&self, specifier: &str, base_dir: &Path, kind: ResolveKind) -> Option<PathBuf> {
+        let (name, subpath) = split_package(specifier)?;
+        let package_dir = self.find_package_dir(name, base_dir)?;
+        self.resolve_package(&package_dir, &subpath, kind)
+    }
+
+    /// 从 base_dir 开始一直往上找 node_modules/<name>。
+    /// npm、yarn、bun 都是这样的结构，monorepo 里的依赖通常被提升到了 workspace 根目录的 node_modules
+    fn find_package_dir(&self, name: &str, base_dir: &Path) -> Option<PathBuf> {
+        base_dir
+            .ancestors()
+            .map(|dir| dir.join("node_modules").join(name))
+            .find(|candidate| candidate.is_dir())
+            .or_else(|| self.find_in_pnpm_store(name))
+    }
+
+    /// pnpm 没有提升到顶层的依赖只在 .pnpm 里，比如 .pnpm/react-dom@18.2.0_react@18.2.0/node_modules/react-dom，
+    /// 同一个包可能有好几个版本，按 lock 文件里锁定的版本挑
+    fn find_in_pnpm_store(&self, name: &str) -> Option<PathBuf> {
+        let store = self
+            .package_manager
+            .workspace_root
+            .join("node_modules/.pnpm");
+        let hoisted = store.join("node_modules").join(name);
+        if hoisted.is_dir() {
+            return Some(hoisted);
+        }
+
+        // 目录名里 scoped 包的 / 换成了 +
+        let prefix = format!("{}@", name.replace('/', "+"));
+        let mut candidates: Vec<String> = std::fs::read_dir(&store)
+            .ok()?
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|dir_name| dir_name.starts_with(&prefix))
+            .collect();
+        candidates.sort();
+
+        let locked = self.package_manager.locked_versions(name);
+        let version_of = |dir_name: &str| {
+            let version = &dir_name[prefix.len()..];
+            version
+                .split(['_', '('])
+                .next()
+                .unwrap_or(version)
+                .to_string()
+        };
+        let picked = candidates
+            .iter()
+            .find(|dir_name| locked.contains(&version_of(dir_name)))
+            .or_else(|| candidates.last())?;
+        let package_dir = store.join(picked).join("node_modules").join(name);
+        package_dir.is_dir().then_some(package_dir)
+    }
+
+    /// 包里面的子路径，subpath 是 "." 或者 "./client" 这种形式
+    fn resolve_package(
+        &self,
+        package_dir: &Path,
+        subpath: &str,
+        kind: ResolveKind,
+    ) -> Option<PathBuf> {
+        let package = self.package_json(package_dir);
+        // 有 exports 的包只能导入 exports 里写了的路径
+        if let Some(exports) = package.as_ref().and_then(|p| p.field("exports")) {
+            return self.resolve_exports(package_dir, exports, subpath, kind);
+        }
+        if subpath == "." {
+            return self.resolve_entry(package_dir);
+        }
+        self.probe(&package_dir.join(subpath))
+    }
+
+    fn resolve_exports(
+        &self,
+        package_dir: &Path,
+        exports: &Value,
+        subpath: &str,
+        kind: ResolveKind,
+    ) -> Option<PathBuf> {
+        // "exports": "./index.js" 或者 { "import": ..., "require": ... } 都是 "." 的简写
+        let is_subpath_map = exports
+            .as_object()
+            .map(|map| map.keys().all(|key| key.starts_with('.')))
+            .unwrap_or(false);
+        if !is_subpath_map {
+            return (subpath == ".")
+                .then(|| self.resolve_target(package_dir, exports, None, kind))
+                .flatten();
+        }
+        let (target, matched) = match_subpath(exports.as_object()?, subpath)?;
+        self.resolve_target(package_dir, target, matched.as_deref(), kind)
+    }
+
+    /// '#internal/utils' 这种，在所在包的 package.json 的 imports 里找
+    fn resolve_imports(
+        &self,
+        specifier: &str,
+        base_dir: &Path,
+        kind: ResolveKind,
+    ) -> Option<PathBuf> {
+        let package = self.nearest_package(base_dir)?;
+        let imports = package.field("imports")?.as_object()?;
+        let (target, matched) = match_subpath(imports, specifier)?;
+        // imports 可以映射到另一个第三方依赖
+        if let Some(target) = target.as_str() {
+            if is_bare_import(target) && !target.starts_with('#') {
+                let target = match &matched {
+                    Some(matched) => target.replace('*', matched),
+                    None => target.to_string(),
+                };
+                return self.resolve_bare(&target, &package.dir, kind);
+            }
+        }
+        self.resolve_target(&package.dir, target, matched.as_deref(), kind)
+    }
+
+    /// exports / imports 里的值：字符串、按条件匹配的对象、依次尝试的数组，null 表示不允许导入
+    fn resolve_target(
+        &self,
+        package_dir: &Path,
+        target: &Value,
+        matched: Option<&str>,
+        kind: ResolveKind,
+    ) -> Option<PathBuf> {
+        match target {
+            Value::String(target) => {
+                if !target.starts_with("./") {
+                    return None;
+                }
+                let target = match matched {
+                    Some(matched) => target.replace('*', matched),
+                    None => target.clone(),
+                };
+                let file = package_dir.join(target);
+                // exports 里写的是确切的文件，不会再补后缀
+                if file.is_file() {
+                    file.canonicalize().ok()
+                } else {
+                    None
+                }
+            }
+            Value::Array(targets) => targets
+                .iter()
+                .find_map(|target| self.resolve_target(package_dir, target, matched, kind)),
+            Value::Object(conditions) => conditions
+                .iter()
+                .filter(|(condition, _)| self.matches_condition(condition, kind))
+                .find_map(|(_, target)| self.resolve_target(package_dir, target, matched, kind)),
+            _ => None,
+        }
+    }
+
+    fn matches_condition(&self, condition: &str, kind: ResolveKind) -> bool {
+        match condition {
+            "default" => true,
+            "import" => kind == ResolveKind::Import,
+            "require" => kind == ResolveKind::Require,
+            _ => self.conditions.iter().any(|c| c == condition),
+        }
+    }
+
+    /// 没有 exports 的包：browser（字符串的时候）、module、main，最后是 index
+    fn resolve_entry(&self, package_dir: &Path) -> Option<PathBuf> {
+        if let Some(package) = self.package_json(package_dir) {
+            for field in MAIN_FIELDS {
+                if let Some(entry) = package.field(field).and_then(Value::as_str) {
+                    if let Some(file) = self.probe_file(&package_dir.join(entry)) {
+                        return Some(file);
+                    }
+                }
+            }
+        }
+        self.probe_file(&package_dir.join("index"))
+    }
+
+    /// 找到真正的文件：补全后缀，文件夹的话找 package.json 里的入口或者 index
+    pub fn probe(&self, path: &Path) -> Option<PathBuf> {
+        if let Some(file) = self.probe_file(path) {
+            return Some(file);
+        }
+        if path.is_dir() {
+            return self.resolve_entry(path);
+        }
+        None
+    }
+
+    fn probe_file(&self, path: &Path) -> Option<PathBuf> {
+        if path.is_file() {
+            return path.canonicalize().ok();
+        }
+        for ext in EXTENSIONS {
+            let with_ext = PathBuf::from(format!("{}.{}", path.display(), ext));
+            if with_ext.is_file() {
+                return with_ext.canonicalize().ok();
+            }
+        }
+        if path.is_dir() {
+            for ext in EXTENSIONS {
+                let index = path.join(format!("index.{}", ext));
+                if index.is_file() {
+                    return index.canonicalize().ok();
+                }
+            }
+        }
+        None
+    }
+
+    /// browser 字段里对第三方依赖的替换，比如 { "fs": false, "buffer": "./shim/buffer.js" }
+    fn browser_mapping(
+        &self,
+        specifier: &str,
+        base_dir: &Path,
+        kind: ResolveKind,
+    ) -> Option<Option<PathBuf>> {
+        let package = self.nearest_package(base_dir)?;
+        let mapped = package.field("browser")?.as_object()?.get(specifier)?;
+        Some(match mapped {
+            Value::Bool(false) => Some(PathBuf::from(EMPTY_MODULE)),
+            Value::String(target) if is_bare_import(target) => {
+                self.resolve_bare(target, &package.dir, kind)
+            }
+            Value::String(target) => self.probe(&package.dir.join(target)),
+            _ => None,
+        })
+    }
+
+    /// browser 字段里对包内文件的替换，比如 { "./lib/node.js": "./lib/browser.js" }
+    fn browser_replacement(&self, file: PathBuf, kind: ResolveKind) -> PathBuf {
+        let Some(package) = file.parent().and_then(|dir| self.nearest_package(dir)) else {
+            return file;
+        };
+        let Some(browser) = package.field("browser").and_then(Value::as_object) else {
+            return file;
+        };
+        for (key, value) in browser {
+            if !key.starts_with('.') {
+                continue;
+            }
+            if self.probe_file(&package.dir.join(key)).as_ref() != Some(&file) {
+                continue;
+            }
+            return match value {
+                Value::Bool(false) => PathBuf::from(EMPTY_MODULE),
+                Value::String(target) if is_bare_import(target) => self
+                    .resolve_bare(target, &package.dir, kind)
+                    .unwrap_or(file),
+                Value::String(target) => self.probe(&package.dir.join(target)).unwrap_or(file),
+                _ => file,
+            };
+        }
+        file
+    }
+
+    /// 文件所在的包：从 dir 开始往上找到的第一个 package.json
+    fn nearest_package(&self, dir: &Path) -> Option<Arc<PackageJson>> {
+        dir.ancestors().find_map(|dir| self.package_json(dir))
+    }
+
+    fn package_json(&self, dir: &Path) -> Option<Arc<PackageJson>> {
+        let mut packages = self.packages.lock().ok()?;
+        if let Some(package) = packages.get(dir) {
+            return package.clone();
+        }
+        let package = fs::read_file_content(dir.join("package.json"))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .map(|json| {
+                Arc::new(PackageJson {
+                    dir: dir.to_path_buf(),
+                    json,
+                })
+            });
+        packages.insert(dir.to_path_buf(), package.clone());
+        package
+    }
+}
+
+/// 'react-dom/client' => ("react-dom", "./client")，'@scope/pkg/sub' => ("@scope/pkg", "./sub")
+pub fn split_package(specifier: &str) -> Option<(&str, String)> {
+    let name_end = if specifier.starts_with('@') {
+        let scope_end = specifier.find('/')?;
+        specifier[scope_end + 1..]
+            .find('/')
+            .map(|end| scope_end + 1 + end)
+            .unwrap_or(specifier.len())
+    } else {
+        specifier.find('/').unwrap_or(specifier.len())
+    };
+    let (name, rest) = specifier.split_at(name_end);
+    if name.is_empty() || name.ends_with('/') {
+        return None;
+    }
+    let subpath = if rest.is_empty() {
+        ".".to_string()
+    } else {
+        format!(".{}", rest)
+    };
+    Some((name, subpath))
+}
+
+/// 在 exports / imports 里找 subpath 对应的值：先找完全一样的 key，
+/// 再找 "./features/*" 这种通配的（前缀最长的优先），返回值和 * 匹配到的部分
+fn match_subpath<'v>(
+    map: &'v Map<String, Value>,
+    subpath: &str,
+) -> Option<(&'v Value, Option<String>)> {
+    if let Some(target) = map.get(subpath) {
+        if !subpath.contains('*') {
+            return Some((target, None));
+        }
+    }
+    let mut best: Option<(usize, &Value, String)> = None;
+    for (key, target) in map {
+        let Some((prefix, suffix)) = key.split_once('*') else {
+            continue;
+        };
+        let matches = subpath.len() >= prefix.len() + suffix.len()
+            && subpath.starts_with(prefix)
+            && subpath.ends_with(suffix);
+        if matches
+            && best
+                .as_ref()
+                .map(|(len, _, _)| prefix.len() > *len)
+                .unwrap_or(true)
+        {
+            let matched = subpath[prefix.len()..subpath.len() - suffix.len()].to_string();
+            best = Some((prefix.len(), target, matched));
+        }
+    }
+    best.map(|(_, target, matched)| (target, Some(matched)))
+}
@@ -0,0 +1,181 @@
+use crate::utils::config::Config;
+use crate::utils::fs;
+use crate::utils::import_analysis::{analyze_imports, is_bare_import};
+use crate::utils::transform::transform_typescript;
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+/// 导入路径没写后缀的时候依次尝试
+const EXTENSIONS: [&str; 5] = ["tsx", "ts", "jsx", "js", "mjs"];
+
+/// 从 index.html 出发，扫描所有能走到的源码文件，收集里面导入的第三方依赖
+///
+/// 启动的时候就把这些依赖一次性预构建好，避免浏览器请求时才一个个构建
+pub fn scan_deps(config: &Config) -> BTreeSet<String> {
+    let root = config.root.as_path();
+    let mut deps = BTreeSet::new();
+    let mut queue = VecDeque::new();
+    let mut seen = HashSet::new();
+
+    let html = match fs::read_file_content(root.join("index.html")) {
+        Ok(html) => html,
+        Err(_) => return deps,
+    };
+    for script in scan_html_scripts(&html) {
+        match script {
+            HtmlScript::Src(src) => {
+                if let Some(file) = resolve_file(root, &root.join(src.trim_start_matches('/'))) {
+                    queue.push_back(file);
+                }
+            }
+            HtmlScript::Inline(code) => {
+                collect_imports(config, root, &code, &mut deps, &mut queue);
+            }
+        }
+    }
+
+    while let Some(file) = queue.pop_front() {
+        if !seen.insert(file.clone()) {
+            continue;
+        }
+        let content = match fs::read_file_content(&file) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        let ext = file.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        // ts 要先转成 js，import type 这类只有类型的导入会被去掉
+        let code = match ext {
+            // 转换失败的文件先跳过，请求到的时候开发服务器会报错
+            "ts" | "tsx" | "jsx" => {
+                match transform_typescript(&content, ext != "ts", false, config, None) {
+                    Ok(output) => output.code,
+                    Err(_) => continue,
+                }
+            }
+            _ => content,
+        };
+        let dir = file.parent().unwrap_or(root).to_path_buf();
+        collect_imports(config, &dir, &code, &mut deps, &mut queue);
+    }
+
+    deps
+}
+
+fn collect_imports(
+    config: &Config,
+    dir: &Path,
+    code: &str,
+    deps: &mut BTreeSet<String>,
+    queue: &mut VecDeque<PathBuf>,
+) {
+    let root = config.root.as_path();
+    for import in analyze_imports(code).imports {
+        // 别名可能指向源码目录，也可能指向另一个依赖
+        let specifier = config
+            .apply_alias(&import.specifier)
+            .unwrap_or(import.specifier);
+        if specifier.starts_with("http") || specifier.starts_with("data:") {
+            continue;
+        }
+        if is_bare_import(&specifier) {
+            deps.insert(specifier);
+            continue;
+        }
+        let path = if specifier.starts_with('/') {
+            root.join(specifier.trim_start_matches('/'))
+        } else {
+            dir.join(&specifier)
+        };
+        if let Some(file) = resolve_file(root, &path) {
+            queue.push_back(file);
+        }
+    }
+}
+
+/// 只继续扫描 js / ts 文件，css、图片之类的不会再导入第三方依赖
+fn resolve_file(root: &Path, path: &Path) -> Option<PathBuf> {
+    if !path.starts_with(root) {
+        return None;
+    }
+    let is_script = |p: &Path| {
+        p.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| EXTENSIONS.contains(&ext))
+            .unwrap_or(false)
+    };
+    if path.is_file() {
+        return is_script(path).then(|| path.to_path_buf());
+    }
+    for ext in EXTENSIONS {
+        let with_ext = PathBuf::from(format!("{}.{}", path.display(), ext));
+        if with_ext.is_file() {
+            return Some(with_ext);
+        }
+        let index = path.join(format!("index.{}", ext));
+        if index.is_file() {
+            return Some(index);
+        }
+    }
+    None
+}
+
+pub enum HtmlScript {
+    Src(String),
+    Inline(String),
+}
+
+/// 找出 html 里所有 <script type="module">，有 src 的记录路径，没有的记录内联代码
+pub fn scan_html_scripts(html: &str) -> Vec<HtmlScript> {
+    let mut scripts = Vec::new();
+    let mut rest = html;
+    while let Some(start) = rest.find("<script") {
+        rest = &rest[start..];
+        let tag_end = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+        let tag = &rest[..tag_end];
+        rest = &rest[tag_end + 1..];
+        let close = rest.find("</script>").unwrap_or(rest.len());
+        let body = &rest[..close];
+        rest = &rest[close..];
+
+        if get_attr(tag, "type").as_deref() != Some("module") {
+            continue;
+        }
+        match get_attr(tag, "src") {
+            Some(src) => scripts.push(HtmlScript::Src(src)),
+            None => scripts.push(HtmlScript::Inline(body.to_string())),
+        }
+    }
+    scripts
+}
+
+/// 找出 html 里所有 <link rel="stylesheet"> 的 href
+pub fn scan_html_stylesheets(html: &str) -> Vec<String> {
+    html.match_indices("<link")
+        .filter_map(|(start, _)| {
+            let rest = &html[start..];
+            let tag = &rest[..rest.find('>')?];
+            if get_attr(tag, "rel").as_deref() != Some("stylesheet") {
+                return None;
+            }
+            get_attr(tag, "href")
+        })
+        .collect()
+}
+
+fn get_attr(tag: &str, name: &str) -> Option<String> {
+    for quote in ['"', '\''] {
+        let pattern = format!("{}={}", name, quote);
+        for (start, _) in tag.match_indices(&pattern) {
+            // 排除 data-src 这种名字里包含 src 的属性
+            if !tag[..start].ends_with(char::is_whitespace) {
+                continue;
+            }
+            let value = &tag[start + pattern.len()..];
+            return value.find(quote).map(|end| value[..end].to_string());
+        }
+    }
+    None
+}
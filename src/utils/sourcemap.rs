@@ -0,0 +1,156 @@
+use base64::Engine;
+use oxc_sourcemap::{SourceMap, Token};
+use std::sync::Arc;
+
+const INLINE_MAP_PREFIX: &str = "//# sourceMappingURL=data:application/json;";
+
+/// 对一段代码做的一组文本替换。
+/// 转换之后的代码还要改导入路径、在前后加代码，用它记下改了哪里，
+/// 就能把 source map 里的位置挪到改完的代码上，不用重新生成
+#[derive(Debug, Default)]
+pub struct CodeEdits {
+    edits: Vec<(usize, usize, String)>,
+}
+
+impl CodeEdits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 把 start..end 换成 text，位置都是原来代码里的字节位置，不能互相重叠
+    pub fn replace(&mut self, start: usize, end: usize, text: impl Into<String>) {
+        self.edits.push((start, end, text.into()));
+    }
+
+    pub fn insert(&mut self, at: usize, text: impl Into<String>) {
+        self.replace(at, at, text);
+    }
+
+    pub fn apply(&mut self, code: &str) -> String {
+        self.sort();
+        let mut result = String::with_capacity(code.len());
+        let mut last = 0;
+        for (start, end, text) in &self.edits {
+            result.push_str(&code[last..*start]);
+            result.push_str(text);
+            last = *end;
+        }
+        result.push_str(&code[last..]);
+        result
+    }
+
+    /// map 是 old_code 的 source map，返回对应 new_code（apply 的结果）的 source map
+    pub fn remap(&mut self, map: &SourceMap, old_code: &str, new_code: &str) -> SourceMap {
+        self.sort();
+        let old_lines = LineIndex::new(old_code);
+        let new_lines = LineIndex::new(new_code);
+        let tokens = map
+            .get_tokens()
+            .map(|token| {
+                let offset = old_lines.offset(token.get_dst_line(), token.get_dst_col());
+                let (line, col) = new_lines.position(self.map_offset(offset));
+                Token::new(
+                    line,
+                    col,
+                    token.get_src_line(),
+                    token.get_src_col(),
+                    token.get_source_id(),
+                    token.get_name_id(),
+                )
+            })
+            .collect();
+        SourceMap::new(
+            map.get_file().map(Arc::from),
+            map.get_names().map(Arc::from).collect(),
+            map.get_source_root().map(str::to_string),
+            map.get_sources().map(Arc::from).collect(),
+            map.get_source_contents()
+                .map(|contents| contents.map(Arc::from).collect()),
+            tokens,
+            None,
+        )
+    }
+
+    fn sort(&mut self) {
+        // 同一个位置的插入保持添加的顺序
+        self.edits.sort_by_key(|(start, end, _)| (*start, *end));
+    }
+
+    /// 原来代码里的位置在新代码里的位置，被替换掉的部分对应到替换内容的开头
+    fn map_offset(&self, offset: usize) -> usize {
+        let mut delta: isize = 0;
+        for (start, end, text) in &self.edits {
+            if *end <= offset {
+                delta += text.len() as isize - (end - start) as isize;
+            } else if *start <= offset {
+                return (*start as isize + delta) as usize;
+            } else {
+                break;
+            }
+        }
+        (offset as isize + delta) as usize
+    }
+}
+
+/// 行号、列号和字节位置互相转换，source map 里的列号按 UTF-16 计算
+struct LineIndex<'a> {
+    code: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(code: &'a str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(code.match_indices('\n').map(|(i, _)| i + 1));
+        LineIndex { code, line_starts }
+    }
+
+    fn offset(&self, line: u32, col: u32) -> usize {
+        let Some(&start) = self.line_starts.get(line as usize) else {
+            return self.code.len();
+        };
+        let mut units = 0;
+        for (i, c) in self.code[start..].char_indices() {
+            if units >= col as usize || c == '\n' {
+                return start + i;
+            }
+            units += c.len_utf16();
+        }
+        self.code.len()
+    }
+
+    fn position(&self, offset: usize) -> (u32, u32) {
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let start = self.line_starts[line];
+        let col: usize = self.code[start..offset].chars().map(char::len_utf16).sum();
+        (line as u32, col as u32)
+    }
+}
+
+/// 代码最后一行的 `//# sourceMappingURL=data:...`，拆成代码和 source map
+pub fn extract_inline_map(code: &str) -> (&str, Option<SourceMap>) {
+    let Some(index) = code.rfind(INLINE_MAP_PREFIX) else {
+        return (code, None);
+    };
+    let comment = code[index..].trim_end();
+    if comment.contains('\n') {
+        return (code, None);
+    }
+    let map = comment
+        .split_once("base64,")
+        .and_then(|(_, data)| base64::engine::general_purpose::STANDARD.decode(data).ok())
+        .and_then(|json| String::from_utf8(json).ok())
+        .and_then(|json| SourceMap::from_json_string(&json).ok());
+    match map {
+        Some(map) => {
+            let code = &code[..index];
+            (code.strip_suffix('\n').unwrap_or(code), Some(map))
+        }
+        None => (code, None),
+    }
+}
+
+/// 在代码最后加上内联的 source map
+pub fn append_inline_map(code: &str, map: &SourceMap) -> String {
+    format!("{}\n//# sourceMappingURL={}\n", code, map.to_data_url())
+}
@@ -1,16 +1,87 @@
-use async_std::path::{Path, PathBuf};
-use async_std::stream::StreamExt;
+use crate::utils::config::{Config, JsxRuntimeKind};
 use oxc_allocator::Allocator;
-use oxc_codegen::CodeGenerator;
+use oxc_codegen::{CodeGenerator, CodegenOptions};
+use oxc_diagnostics::OxcDiagnostic;
 use oxc_parser::{ParseOptions, Parser, ParserReturn};
 use oxc_semantic::SemanticBuilder;
+use oxc_sourcemap::SourceMap;
 use oxc_span::SourceType;
-use oxc_transformer::{JsxOptions, JsxRuntime, TransformOptions, Transformer};
-use regex::Regex;
-use serde_json;
+use oxc_transformer::{
+    JsxOptions, JsxRuntime, ReactRefreshOptions, ReplaceGlobalDefines, ReplaceGlobalDefinesConfig,
+    TransformOptions, Transformer,
+};
+use std::path::Path;
+
+/// ts 转换的结果，map 里的源文件名就是传进来的 source_map_path
+pub struct TransformOutput {
+    pub code: String,
+    pub map: Option<SourceMap>,
+}
+
+/// 转换失败的原因和位置，行号、列号从 1 开始。
+/// 开发服务器把它发给浏览器显示在错误浮层上，构建的时候直接输出
+#[derive(Debug, Clone)]
+pub struct TransformError {
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// 出错位置附近的源码
+    pub frame: Option<String>,
+}
+
+impl TransformError {
+    /// 多个错误只报第一个的位置，其他的错误信息放在后面
+    fn from_diagnostics(errors: Vec<OxcDiagnostic>, source: &str) -> Self {
+        let message = errors
+            .iter()
+            .map(|error| error.message.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let offset = errors
+            .first()
+            .and_then(|error| error.labels.as_ref())
+            .and_then(|labels| labels.first())
+            .map(|label| label.offset());
+        let Some(offset) = offset else {
+            return TransformError {
+                message,
+                line: None,
+                column: None,
+                frame: None,
+            };
+        };
+        let (line, column) = line_column(source, offset);
+        TransformError {
+            message,
+            line: Some(line),
+            column: Some(column),
+            frame: Some(code_frame(source, line, column)),
+        }
+    }
+}
 
-/// 转换 ts 和 tsx => js
-pub fn transform_typescript(source: &str, is_tsx: bool) -> String {
+impl std::fmt::Display for TransformError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, " ({}:{})", line, column)?;
+        }
+        if let Some(frame) = &self.frame {
+            write!(f, "\n{}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+/// 转换 ts 和 tsx => js，react_refresh 为 true 时会给组件插入 $RefreshReg$ 注册代码。
+/// jsx 的运行时和 define 替换来自项目配置，传了 source_map_path 的话同时生成 source map
+pub fn transform_typescript(
+    source: &str,
+    is_tsx: bool,
+    react_refresh: bool,
+    config: &Config,
+    source_map_path: Option<&Path>,
+) -> Result<TransformOutput, TransformError> {
     // 设置内存分配器
     let allocator = Allocator::default();
     let source_type = if is_tsx {
@@ -33,7 +104,7 @@ pub fn transform_typescript(source: &str, is_tsx: bool) -> String {
         .parse();
     // println!("ast: \n{:?}\n", program);
     if panicked || !errors.is_empty() {
-        return format!("Parse Error: {:?}", errors);
+        return Err(TransformError::from_diagnostics(errors, source));
     }
 
     let ret = SemanticBuilder::new()
@@ -51,8 +122,16 @@ pub fn transform_typescript(source: &str, is_tsx: bool) -> String {
     let (symbols, scopes) = ret.semantic.into_symbol_table_and_scope_tree();
     let transform_options = TransformOptions {
         jsx: JsxOptions {
-            development: false,           // 使用生产模式
-            runtime: JsxRuntime::Classic, // 使用经典模式，生成 React.createElement
+            development: config.jsx.development,
+            // classic 生成 React.createElement，automatic 从 react/jsx-runtime 导入
+            runtime: match config.jsx.runtime {
+                JsxRuntimeKind::Classic => JsxRuntime::Classic,
+                JsxRuntimeKind::Automatic => JsxRuntime::Automatic,
+            },
+            import_source: config.jsx.import_source.clone(),
+            pragma: config.jsx.pragma.clone(),
+            pragma_frag: config.jsx.pragma_frag.clone(),
+            refresh: react_refresh.then(ReactRefreshOptions::default),
             ..JsxOptions::default()
         },
         ..TransformOptions::enable_all()
@@ -65,22 +144,32 @@ pub fn transform_typescript(source: &str, is_tsx: bool) -> String {
     )
     .build_with_symbols_and_scopes(symbols, scopes, &mut program);
     if !ret.errors.is_empty() {
-        println!("Transformer Errors:");
-        for error in ret.errors {
-            let error = error.with_source_code(source.to_string());
-            println!("{error:?}");
+        return Err(TransformError::from_diagnostics(ret.errors, source));
+    }
+
+    // 配置里的 define，加载配置的时候已经检查过了
+    let defines = config.define_pairs();
+    if !defines.is_empty() {
+        if let Ok(define_config) = ReplaceGlobalDefinesConfig::new(&defines) {
+            let _ = ReplaceGlobalDefines::new(&allocator, define_config).build(
+                ret.symbols,
+                ret.scopes,
+                &mut program,
+            );
         }
     }
-    CodeGenerator::new().build(&program).code
-    // let result = CodeGenerator::new()
-    //     .with_options(CodegenOptions {
-    //         ..CodegenOptions::default()
-    //     })
-    //     .build(&program);
+    let result = CodeGenerator::new()
+        .with_options(CodegenOptions {
+            source_map_path: source_map_path.map(Path::to_path_buf),
+            ..CodegenOptions::default()
+        })
+        .build(&program);
     // codegen.set_source_type(source_type); // 设置源码类型，让它知道要处理 JSX
-    // let result = codegen.build();
 
-    // result.code
+    Ok(TransformOutput {
+        code: result.code,
+        map: result.map,
+    })
 }
 
 // pub async fn transform_typescript(content: &str, is_tsx: bool) -> String {
@@ -96,127 +185,53 @@ pub fn transform_typescript(source: &str, is_tsx: bool) -> String {
 //     result.code
 // }
 
-pub fn is_js_or_ts_file(path: &str) -> bool {
-    path.ends_with(".js")
-        || path.ends_with(".jsx")
-        || path.ends_with(".ts")
-        || path.ends_with(".tsx")
+/// 字节位置 => (行, 列)，都从 1 开始，列按字符算
+pub fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (line, before[line_start..].chars().count() + 1)
 }
 
-pub async fn process_imports(content: String) -> String {
-    let import_re = Regex::new(r#"(?m)^import\s+.*?from\s+["']([^"']+)["']"#).unwrap();
-    let mut result = content.clone();
-
-    for cap in import_re.captures_iter(&content) {
-        let import_path = &cap[1];
-        if !import_path.starts_with('.')
-            && !import_path.starts_with('/')
-            && !import_path.starts_with("http")
-        {
-            // 替换为 /@modules/ 开头的路径
-            let new_path = format!("/@modules/{}", import_path);
-            result = result.replace(&cap[0], &cap[0].replace(import_path, &new_path));
+/// 出错那一行和前后两行，用 ^ 指出出错的列：
+///
+/// ```text
+///   3 | const a = 1;
+/// > 4 | const b = ;
+///     |           ^
+///   5 | export {};
+/// ```
+pub fn code_frame(source: &str, line: usize, column: usize) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    let first = line.saturating_sub(2).max(1);
+    let last = (line + 2).min(lines.len());
+    let width = last.to_string().len();
+    let mut frame = Vec::new();
+    for number in first..=last {
+        let text = lines.get(number - 1).copied().unwrap_or("");
+        let marker = if number == line { ">" } else { " " };
+        frame.push(format!(
+            "{} {:>width$} | {}",
+            marker,
+            number,
+            text,
+            width = width
+        ));
+        if number == line {
+            frame.push(format!(
+                "  {:>width$} | {}^",
+                "",
+                " ".repeat(column.saturating_sub(1)),
+                width = width
+            ));
         }
     }
-
-    result
+    frame.join("\n")
 }
 
-/// 处理特殊标记的模块路径，找到其在 node_modules 中的具体位置
-pub async fn resolve_module_path(root_dir: &str, module_path: &str) -> Option<PathBuf> {
-    // 将模块路径拆分为包名和子路径，这是为了处理一种复杂的情况：
-    // 比如，react-dom/client 在 pnpm 下的位置是：
-    // node_modules/.pnpm/react-dom@xxx/node_modules/react-dom/client.js
-    let parts: Vec<&str> = module_path.splitn(2, '/').collect();
-
-    let (package_name, sub_path) = match parts.as_slice() {
-        [pkg] => (*pkg, None),
-        [pkg, path] => (*pkg, Some(*path)),
-        _ => return None,
-    };
-
-    let pnpm_lock = Path::new(root_dir).join("pnpm-lock.yaml");
-    let npm_lock = Path::new(root_dir).join("package-lock.json");
-
-    let base_path = if async_std::fs::metadata(&pnpm_lock).await.is_ok() {
-        // 首先尝试 .pnpm/node_modules 路径
-        let pnpm_modules_path = Path::new(root_dir)
-            .join("node_modules/.pnpm/node_modules")
-            .join(package_name);
-
-        if async_std::fs::metadata(&pnpm_modules_path).await.is_ok() {
-            pnpm_modules_path
-        } else {
-            // 如果找不到，尝试在 .pnpm 目录下查找带版本号的包
-            let pnpm_dir = Path::new(root_dir).join("node_modules/.pnpm");
-
-            // 读取目录内容——版本号需要读取 pnpm-lock.yaml 等文件来确定
-            if let Ok(mut entries) = async_std::fs::read_dir(&pnpm_dir).await {
-                while let Some(entry) = entries.next().await {
-                    if let Ok(entry) = entry {
-                        let file_name = entry.file_name().into_string().unwrap_or_default();
-                        if file_name.starts_with(&format!("{package_name}@")) {
-                            let package_path = entry.path().join("node_modules").join(package_name);
-                            if async_std::fs::metadata(&package_path).await.is_ok() {
-                                return resolve_package_entry(&package_path, sub_path).await;
-                            }
-                        }
-                    }
-                }
-            }
-            pnpm_modules_path
-        }
-    } else if async_std::fs::metadata(&npm_lock).await.is_ok() {
-        Path::new(root_dir).join("node_modules").join(package_name)
-    } else {
-        panic!("No lock file found. Please run `npm install` or `pnpm install` first.")
-    };
-
-    // （如果是形如 react-dom/client， 有二级路径，那么还需要进一步解析）
-    // 找到具体的入口文件，比如什么 main.js index.js 之类的
-    resolve_package_entry(&base_path, sub_path).await
-}
-
-async fn resolve_package_entry(package_path: &Path, sub_path: Option<&str>) -> Option<PathBuf> {
-    if !async_std::fs::metadata(package_path).await.is_ok() {
-        return None;
-    }
-
-    // 如果有子路径，直接尝试解析
-    if let Some(sub) = sub_path {
-        let full_path = package_path.join(sub);
-
-        // 比如 react-dom/client,
-        // 由于不知道 client.xx 到底是什么文件（还可能是文件夹），所以这里遍历下。。。
-        let extensions = [".js", ".ts", "/index.js", "/index.ts"];
-        for ext in extensions.iter() {
-            let path_with_ext = if sub.ends_with(".js") {
-                full_path.clone()
-            } else {
-                full_path.with_extension(ext.trim_start_matches('.'))
-            };
-
-            if async_std::fs::metadata(&path_with_ext).await.is_ok() {
-                return Some(path_with_ext);
-            }
-        }
-    }
-
-    // 如果没有子路径或子路径不存在，尝试读取 package.json
-    let package_json = package_path.join("package.json");
-    if let Ok(content) = async_std::fs::read_to_string(&package_json).await {
-        if let Ok(json) = serde_json::from_str::<serde_json::Value>(&content) {
-            if let Some(entry) = json.get("module").or_else(|| json.get("main")) {
-                if let Some(entry_path) = entry.as_str() {
-                    return Some(package_path.join(entry_path));
-                }
-            }
-        }
-    }
-
-    // 如果以上都没找到，那么就报错
-    panic!(
-        "No entry file found for package: {}",
-        package_path.display()
-    );
+pub fn is_js_or_ts_file(path: &str) -> bool {
+    path.ends_with(".js")
+        || path.ends_with(".jsx")
+        || path.ends_with(".ts")
+        || path.ends_with(".tsx")
 }
@@ -0,0 +1,56 @@
+use crate::utils::watcher::{WatchEvent, WatchListener};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// 转换之后的代码，和它依赖的其他文件、import.meta.glob 匹配的目录
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub code: String,
+    pub watch_files: Vec<PathBuf>,
+    pub watch_dirs: Vec<PathBuf>,
+}
+
+/// 按模块 id 缓存转换结果，文件没变就不用每次请求都重新解析、转换。
+/// 同一个文件带不同的查询参数（/data.json 和 /data.json?import）是不同的结果。
+/// 每个结果还记着它依赖的其他文件（比如 css 里 @import 的文件），它们变了结果也失效；
+/// glob 匹配的目录里有文件变化（包括新建、删除）也失效
+#[derive(Debug, Clone, Default)]
+pub struct TransformCache {
+    entries: Arc<RwLock<HashMap<String, (PathBuf, Entry)>>>,
+}
+
+impl TransformCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn get(&self, id: &str) -> Option<Entry> {
+        self.entries
+            .read()
+            .await
+            .get(id)
+            .map(|(_, entry)| entry.clone())
+    }
+
+    pub async fn insert(&self, id: String, file: PathBuf, entry: Entry) {
+        self.entries.write().await.insert(id, (file, entry));
+    }
+
+    pub async fn invalidate(&self, file: &Path) {
+        self.entries.write().await.retain(|_, (cached, entry)| {
+            cached != file
+                && !entry.watch_files.iter().any(|f| f == file)
+                && !entry.watch_dirs.iter().any(|dir| file.starts_with(dir))
+        });
+    }
+}
+
+#[async_trait::async_trait]
+impl WatchListener for TransformCache {
+    async fn on_change(&self, event: &WatchEvent) {
+        // 不管是修改还是删除，旧的转换结果都不能再用了
+        self.invalidate(&event.path).await;
+    }
+}
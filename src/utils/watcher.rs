@@ -0,0 +1,207 @@
+use crate::utils::config::Config;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 编辑器保存一次文件可能触发好几个事件，收集一小段时间内的事件再统一处理
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// npm install 之类的操作会在一段时间里不停地改 node_modules，等它停下来再一起处理
+const DEPENDENCY_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// 依赖相关的文件，变化之后预构建的产物就不可信了
+const DEPENDENCY_FILES: [&str; 6] = [
+    "package.json",
+    "pnpm-lock.yaml",
+    "package-lock.json",
+    "yarn.lock",
+    "bun.lockb",
+    "bun.lock",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchEventKind {
+    Create,
+    Change,
+    Remove,
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchEvent {
+    pub path: PathBuf,
+    pub kind: WatchEventKind,
+}
+
+impl WatchEvent {
+    /// package.json、lock 文件或者 node_modules 里的变化
+    pub fn is_dependency_change(&self, config: &Config) -> bool {
+        is_dependency_file(&self.path, config)
+    }
+}
+
+fn is_dependency_file(path: &Path, config: &Config) -> bool {
+    // 缓存目录是我们自己写的预构建产物，不算依赖变化
+    if path.starts_with(config.cache_dir()) {
+        return false;
+    }
+    let relative = match path.strip_prefix(&config.root) {
+        Ok(relative) => relative,
+        Err(_) => return false,
+    };
+    if relative.starts_with("node_modules") {
+        return true;
+    }
+    relative
+        .to_str()
+        .map(|name| DEPENDENCY_FILES.contains(&name))
+        .unwrap_or(false)
+}
+
+/// 订阅文件变化的子系统（转换缓存、依赖缓存、HMR、日志……）都实现这个 trait
+#[async_trait::async_trait]
+pub trait WatchListener: Send + Sync {
+    async fn on_change(&self, event: &WatchEvent);
+}
+
+/// 监听项目目录，把一批文件变化按订阅顺序依次分发给各个 listener，
+/// 前一个 listener 处理完这一批的所有事件，后一个才开始
+///
+/// 顺序是有意义的：缓存要先失效，HMR 再通知浏览器重新请求，不然浏览器可能拿到旧的内容
+pub struct FileWatcher {
+    config: Arc<Config>,
+    listeners: Vec<Arc<dyn WatchListener>>,
+}
+
+impl FileWatcher {
+    pub fn new(config: Arc<Config>) -> Self {
+        FileWatcher {
+            config,
+            listeners: Vec::new(),
+        }
+    }
+
+    pub fn subscribe(&mut self, listener: Arc<dyn WatchListener>) {
+        self.listeners.push(listener);
+    }
+
+    pub fn start(self) -> notify::Result<()> {
+        let (tx, rx) = async_std::channel::unbounded::<WatchEvent>();
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+                if let Ok(event) = res {
+                    let kind = match event.kind {
+                        EventKind::Create(_) => WatchEventKind::Create,
+                        EventKind::Modify(_) => WatchEventKind::Change,
+                        EventKind::Remove(_) => WatchEventKind::Remove,
+                        _ => return,
+                    };
+                    for path in event.paths {
+                        // 重命名也是 Modify 事件，旧的路径已经不存在了
+                        let kind = if kind == WatchEventKind::Change && !path.exists() {
+                            WatchEventKind::Remove
+                        } else {
+                            kind
+                        };
+                        let _ = tx.try_send(WatchEvent { path, kind });
+                    }
+                }
+            })?;
+
+        for (path, mode) in watch_targets(&self.config)? {
+            watcher.watch(&path, mode)?;
+        }
+
+        async_std::task::spawn(async move {
+            // watcher 被 drop 之后就不再监听了，所以把它移进任务里
+            let _watcher = watcher;
+            while let Ok(first) = rx.recv().await {
+                // 同一个文件只保留最后一次事件，新建之后紧接着写入内容还算新建
+                let mut events: HashMap<PathBuf, WatchEventKind> = HashMap::new();
+                events.insert(first.path, first.kind);
+                async_std::task::sleep(DEBOUNCE).await;
+                collect(&rx, &mut events);
+                // 依赖变化要等更久，直到一段时间内都没有新的事件
+                if events
+                    .keys()
+                    .any(|path| is_dependency_file(path, &self.config))
+                {
+                    async_std::task::sleep(DEPENDENCY_DEBOUNCE).await;
+                    while collect(&rx, &mut events) {
+                        async_std::task::sleep(DEPENDENCY_DEBOUNCE).await;
+                    }
+                }
+
+                let events: Vec<WatchEvent> = events
+                    .into_iter()
+                    .map(|(path, kind)| WatchEvent { path, kind })
+                    .collect();
+                for listener in &self.listeners {
+                    for event in &events {
+                        listener.on_change(event).await;
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+/// 把已经到达的事件合并进来，返回有没有新的事件
+fn collect(
+    rx: &async_std::channel::Receiver<WatchEvent>,
+    events: &mut HashMap<PathBuf, WatchEventKind>,
+) -> bool {
+    let mut received = false;
+    while let Ok(event) = rx.try_recv() {
+        let kind = match (events.get(&event.path), event.kind) {
+            (Some(WatchEventKind::Create), WatchEventKind::Change) => WatchEventKind::Create,
+            (_, kind) => kind,
+        };
+        events.insert(event.path, kind);
+        received = true;
+    }
+    received
+}
+
+/// 需要监听的路径：根目录本身只监听第一层（package.json、lock 文件等），
+/// node_modules 太大了，只监听它的第一层（包的增删）和 package.json 里 dependencies 的包
+/// （patch-package、手改这些包的文件），构建产物不用监听，其他文件夹递归监听
+fn watch_targets(config: &Config) -> std::io::Result<Vec<(PathBuf, RecursiveMode)>> {
+    let root_dir = config.root.as_path();
+    let out_dir = config.out_dir();
+    let mut targets = vec![(root_dir.to_path_buf(), RecursiveMode::NonRecursive)];
+    for entry in std::fs::read_dir(root_dir)?.flatten() {
+        let path = entry.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name == "node_modules" {
+            for dep in direct_dependencies(root_dir) {
+                let package_dir = path.join(dep);
+                if package_dir.is_dir() {
+                    targets.push((package_dir, RecursiveMode::Recursive));
+                }
+            }
+            targets.push((path, RecursiveMode::NonRecursive));
+        } else if !name.starts_with('.') && name != "target" && path != out_dir {
+            targets.push((path, RecursiveMode::Recursive));
+        }
+    }
+    Ok(targets)
+}
+
+/// package.json 里 dependencies 的包名
+fn direct_dependencies(root_dir: &Path) -> Vec<String> {
+    std::fs::read_to_string(root_dir.join("package.json"))
+        .ok()
+        .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+        .and_then(|json| {
+            json.get("dependencies")
+                .and_then(|deps| deps.as_object())
+                .map(|deps| deps.keys().cloned().collect())
+        })
+        .unwrap_or_default()
+}